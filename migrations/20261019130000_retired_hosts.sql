@@ -0,0 +1 @@
+ALTER TABLE hosts ADD COLUMN retired_at timestamptz NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE groups ADD COLUMN parent_id INTEGER NULL REFERENCES groups (id) ON DELETE RESTRICT;
+-- root (id 0) is the top of the groups tree, every other group is its descendant
+UPDATE groups SET parent_id = 0 WHERE id <> 0;
+ALTER TABLE groups
+    ALTER parent_id SET DEFAULT 0,
+    ADD CONSTRAINT groups_parent_is_not_self CHECK (parent_id <> id),
+    ADD CONSTRAINT groups_only_root_is_top_level CHECK ((parent_id IS NULL) = (id = 0));
@@ -0,0 +1,7 @@
+CREATE TABLE group_lease_policies (
+    group_id INTEGER PRIMARY KEY REFERENCES groups (id) ON DELETE CASCADE,
+    -- NULL means that the value is inherited from the parent group or global settings
+    max_lease_hours INTEGER NULL CHECK (max_lease_hours > 0),
+    default_lease_hours INTEGER NULL CHECK (default_lease_hours > 0),
+    user_limit INTEGER NULL CHECK (user_limit >= 0)
+);
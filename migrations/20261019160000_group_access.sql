@@ -0,0 +1,6 @@
+-- Groups without rows here are open to everyone, unless their parent group is restricted
+CREATE TABLE group_access (
+    group_id INTEGER NOT NULL REFERENCES groups (id) ON DELETE CASCADE,
+    ad_group TEXT NOT NULL,
+    PRIMARY KEY (group_id, ad_group)
+);
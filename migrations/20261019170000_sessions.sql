@@ -0,0 +1,7 @@
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    record JSONB NOT NULL,
+    expiry_date TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_expiry_date_idx ON sessions (expiry_date);
@@ -0,0 +1,7 @@
+-- Users of the local authentication provider
+CREATE TABLE local_users (
+    login TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    email TEXT NOT NULL,
+    groups TEXT[] NOT NULL DEFAULT '{}'
+);
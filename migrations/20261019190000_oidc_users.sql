@@ -0,0 +1,9 @@
+-- Claims of users logged in with OpenID Connect as of their last login,
+-- logins are linked to users by `sub` and resolved to existing users by email
+CREATE TABLE oidc_users (
+    sub TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    groups TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
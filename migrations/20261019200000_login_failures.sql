@@ -0,0 +1,9 @@
+-- Failed login attempts by username and by client address
+CREATE TABLE login_failures (
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ NULL,
+    PRIMARY KEY (kind, value)
+);
@@ -0,0 +1,8 @@
+-- Roles are derived from groups and saved on login, the app itself reads them from groups;
+-- the table is only kept for reports and admin tools
+CREATE TYPE user_role AS ENUM ('viewer', 'user', 'admin');
+
+CREATE TABLE user_roles (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    role user_role NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Every lease of a host, open leases have no `released_at`
+CREATE TABLE lease_history (
+    id SERIAL PRIMARY KEY,
+    host_id INTEGER NOT NULL REFERENCES hosts (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    leased_at TIMESTAMPTZ NOT NULL,
+    leased_until TIMESTAMPTZ NOT NULL,
+    released_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX lease_history_leased_at_idx ON lease_history (leased_at);
+CREATE INDEX lease_history_open_idx ON lease_history (host_id) WHERE released_at IS NULL;
+
+-- history starts with leases active at the moment of migration
+INSERT INTO lease_history (host_id, user_id, leased_at, leased_until)
+SELECT id, user_id, now(), leased_until FROM hosts WHERE user_id IS NOT NULL AND leased_until IS NOT NULL;
@@ -0,0 +1,11 @@
+-- wakes up the release timer when leases change, see `LEASES_CHANNEL`
+CREATE FUNCTION notify_leases_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('leases_changed', '');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER hosts_leases_changed
+AFTER INSERT OR DELETE OR UPDATE OF user_id, leased_until ON hosts
+FOR EACH STATEMENT EXECUTE FUNCTION notify_leases_changed();
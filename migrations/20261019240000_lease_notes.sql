@@ -0,0 +1,3 @@
+-- why hosts are leased, set by the user and cleared on release
+ALTER TABLE hosts ADD COLUMN lease_note TEXT NULL, ADD COLUMN lease_ticket TEXT NULL;
+ALTER TABLE lease_history ADD COLUMN lease_note TEXT NULL, ADD COLUMN lease_ticket TEXT NULL;
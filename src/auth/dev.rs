@@ -0,0 +1,42 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use secrecy::SecretString;
+
+use super::AuthProvider;
+use crate::ldap::AdUserInfo;
+
+/// Single user configured in settings which is logged in with any password.
+/// Only meant for development.
+#[derive(Clone)]
+pub struct StaticProvider {
+    login: String,
+    info: AdUserInfo,
+}
+
+impl StaticProvider {
+    pub fn new(login: &str, email: &str, groups: &[String]) -> Self {
+        Self {
+            login: login.into(),
+            info: AdUserInfo {
+                dn: format!("static:{login}"),
+                email: email.into(),
+                groups: groups.to_vec(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for StaticProvider {
+    async fn authenticate(
+        &self,
+        login: &str,
+        _password: &SecretString,
+    ) -> Result<Option<AdUserInfo>> {
+        Ok((login == self.login || login == self.info.email).then(|| self.info.clone()))
+    }
+
+    async fn get_user_info(&self, dn: &str) -> Result<Option<AdUserInfo>> {
+        Ok((dn == self.info.dn).then(|| self.info.clone()))
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{Result, anyhow};
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use async_trait::async_trait;
+use secrecy::{ExposeSecret, SecretString};
+
+use super::AuthProvider;
+use crate::{
+    db::{Registry, models::LocalUser},
+    ldap::AdUserInfo,
+};
+
+/// Users kept in `local_users` table with argon2 password hashes
+#[derive(Clone)]
+pub struct LocalProvider {
+    registry: Registry,
+}
+
+impl LocalProvider {
+    pub fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+
+    /// Creates the user or replaces password, email and groups of the existing one
+    pub async fn set_user(
+        &self,
+        login: &str,
+        password: &SecretString,
+        email: &str,
+        groups: &[String],
+    ) -> Result<()> {
+        if password.expose_secret().is_empty() {
+            return Err(anyhow!("Empty password"));
+        }
+        let hash = Argon2::default()
+            .hash_password(
+                password.expose_secret().as_bytes(),
+                &SaltString::generate(&mut OsRng),
+            )
+            .map_err(|err| anyhow!("Failed to hash password: {err}"))?
+            .to_string();
+
+        let mut tx = self.registry.begin().await?;
+        tx.set_local_user(login, &hash, email, groups).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_user(&self, login: &str) -> Result<Option<LocalUser>> {
+        let mut tx = self.registry.begin().await?;
+        let user = tx.get_local_user(login).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+}
+
+/// Local users don't have a DN, login with a prefix is used instead
+fn local_dn(login: &str) -> String {
+    format!("local:{login}")
+}
+
+impl From<LocalUser> for AdUserInfo {
+    fn from(value: LocalUser) -> Self {
+        Self {
+            dn: local_dn(&value.login),
+            email: value.email,
+            groups: value.groups,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for LocalProvider {
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &SecretString,
+    ) -> Result<Option<AdUserInfo>> {
+        let Some(user) = self.get_user(login).await? else {
+            return Ok(None);
+        };
+        let hash = PasswordHash::new(&user.password_hash)
+            .map_err(|err| anyhow!("Invalid password hash of '{login}': {err}"))?;
+        if Argon2::default()
+            .verify_password(password.expose_secret().as_bytes(), &hash)
+            .is_err()
+        {
+            return Ok(None);
+        }
+        Ok(Some(user.into()))
+    }
+
+    async fn get_user_info(&self, dn: &str) -> Result<Option<AdUserInfo>> {
+        let Some(login) = dn.strip_prefix("local:") else {
+            return Ok(None);
+        };
+        Ok(self.get_user(login).await?.map(Into::into))
+    }
+}
@@ -0,0 +1,83 @@
+mod dev;
+mod local;
+mod oidc;
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use secrecy::SecretString;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+pub use dev::StaticProvider;
+pub use local::LocalProvider;
+pub use oidc::{OidcClient, OidcFlow};
+
+use crate::{
+    configuration::{AuthSettings, Settings},
+    db::Registry,
+    health::HealthCheck,
+    ldap::{AdUserInfo, UsersInfo, pool::LdapPool},
+};
+
+/// Source of users and their groups
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Checks credentials, `None` if they are wrong or user is unknown
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &SecretString,
+    ) -> Result<Option<AdUserInfo>>;
+
+    /// Up to date info of an authenticated user
+    async fn get_user_info(&self, dn: &str) -> Result<Option<AdUserInfo>>;
+
+    /// External services the provider depends on, checked by `/readyz`
+    fn health_checks(&self) -> Vec<(&'static str, Arc<dyn HealthCheck>)> {
+        vec![]
+    }
+}
+
+/// Builds provider selected in `[auth]` section of configuration,
+/// its background tasks stop once `shutdown` is cancelled
+pub async fn build_auth_provider(
+    settings: &Settings,
+    registry: Registry,
+    shutdown: CancellationToken,
+) -> Result<Arc<dyn AuthProvider>> {
+    Ok(match &settings.auth {
+        AuthSettings::Ldap => {
+            let ldap = settings
+                .ldap
+                .clone()
+                .context("[ldap] section is required for LDAP authentication")?;
+            let pool = LdapPool::new(ldap.clone());
+            pool.check().await;
+            let users_info = UsersInfo::new(
+                pool,
+                ldap.users_query.clone(),
+                ldap.schema.clone(),
+                ldap.user_info_cache(),
+            )
+            .await?;
+            tokio::spawn(
+                users_info
+                    .cache
+                    .clone()
+                    .log_stats(Duration::from_secs(10 * 60), shutdown),
+            );
+            Arc::new(users_info)
+        }
+        AuthSettings::Local => Arc::new(LocalProvider::new(registry)),
+        AuthSettings::Static {
+            login,
+            email,
+            groups,
+        } => {
+            warn!("Static user '{login}' is enabled, it can log in with any password");
+            Arc::new(StaticProvider::new(login, email, groups))
+        }
+    })
+}
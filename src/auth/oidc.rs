@@ -0,0 +1,240 @@
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::Utc;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::AuthProvider;
+use crate::{configuration::OidcSettings, db::Registry, ldap::AdUserInfo};
+
+/// Client of an OpenID Connect provider using authorization code flow with PKCE
+#[derive(Clone)]
+pub struct OidcClient {
+    settings: OidcSettings,
+    http: reqwest::Client,
+    registry: Registry,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims of a validated ID token
+struct IdClaims {
+    sub: String,
+    email: String,
+    groups: Vec<String>,
+}
+
+/// Started login, kept in the session until the provider redirects user back
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OidcFlow {
+    pub state: String,
+    nonce: String,
+    verifier: String,
+}
+
+fn random_string() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl OidcClient {
+    pub fn new(settings: OidcSettings, registry: Registry) -> Self {
+        Self {
+            settings,
+            http: reqwest::Client::new(),
+            registry,
+        }
+    }
+
+    async fn metadata(&self) -> Result<ProviderMetadata> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.settings.issuer_url.trim_end_matches('/')
+        );
+        self.http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Invalid OIDC discovery document at {url}"))
+    }
+
+    /// Returns URL of the provider login page to redirect user to
+    pub async fn start(&self) -> Result<(String, OidcFlow)> {
+        let metadata = self.metadata().await?;
+        let flow = OidcFlow {
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+        };
+        let challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(flow.verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.settings.client_id),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("scope", &self.settings.scopes.join(" ")),
+                ("state", &flow.state),
+                ("nonce", &flow.nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+        Ok((url.into(), flow))
+    }
+
+    /// Exchanges authorization code for an ID token and saves user claims.
+    /// First login is linked to the user with the same email, a new user is created if there is none.
+    pub async fn finish(&self, flow: &OidcFlow, code: &str) -> Result<AdUserInfo> {
+        let metadata = self.metadata().await?;
+        let response: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(
+                &self.settings.client_id,
+                Some(self.settings.client_secret.expose_secret()),
+            )
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("code_verifier", &flow.verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()
+            .context("OIDC token request failed")?
+            .json()
+            .await?;
+
+        let claims = self.validate_id_token(&metadata, flow, &response.id_token)?;
+        let mut tx = self.registry.begin().await?;
+        let user = match tx.get_oidc_user(&claims.sub).await? {
+            Some(linked) => tx.get_user_by_id(&linked.user_id).await?,
+            None => tx.get_user_by_mail(&claims.email).await?,
+        };
+        let user = match user {
+            Some(user) => user,
+            None => {
+                let user_id = tx
+                    .add_user(&oidc_dn(&claims.sub), None, &claims.email)
+                    .await?;
+                tx.get_user_by_id(&user_id)
+                    .await?
+                    .context("Created user is missing")?
+            }
+        };
+        tx.set_oidc_user(&claims.sub, &user.id, &claims.email, &claims.groups)
+            .await?;
+        tx.commit().await?;
+        Ok(AdUserInfo {
+            dn: user.dn,
+            email: claims.email,
+            groups: claims.groups,
+        })
+    }
+
+    /// ID token is received directly from the token endpoint,
+    /// so TLS is relied on instead of checking its signature
+    fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        flow: &OidcFlow,
+        id_token: &str,
+    ) -> Result<IdClaims> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .context("ID token is not a JWT")?;
+        let claims: Value = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(payload)?)?;
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str);
+
+        if claim("iss") != Some(&metadata.issuer) {
+            bail!("ID token is issued by '{:?}'", claim("iss"));
+        }
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == &self.settings.client_id,
+            Some(Value::Array(aud)) => aud.iter().any(|a| a == &self.settings.client_id),
+            _ => false,
+        };
+        if !audience_matches {
+            bail!("ID token is issued for another client");
+        }
+        let expires_at = claims.get("exp").and_then(Value::as_i64).unwrap_or(0);
+        if expires_at <= Utc::now().timestamp() {
+            bail!("ID token is expired");
+        }
+        if claim("nonce") != Some(&flow.nonce) {
+            bail!("ID token nonce doesn't match");
+        }
+
+        let sub = claim("sub").context("ID token has no 'sub' claim")?;
+        let email = claim("email").context("ID token has no 'email' claim")?;
+        // users are matched by email, so it must be confirmed by the provider
+        if claims.get("email_verified") != Some(&Value::Bool(true)) {
+            bail!("Email '{email}' of ID token is not verified");
+        }
+        let groups = match claims.get(&self.settings.groups_claim) {
+            None => vec![],
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .map(|g| g.as_str().map(String::from))
+                .collect::<Option<_>>()
+                .ok_or_else(|| anyhow!("Groups claim must be a list of strings"))?,
+            Some(_) => bail!("Groups claim must be a list of strings"),
+        };
+        Ok(IdClaims {
+            sub: sub.into(),
+            email: email.into(),
+            groups,
+        })
+    }
+
+    pub fn is_oidc_user(dn: &str) -> bool {
+        dn.starts_with("oidc:")
+    }
+}
+
+fn oidc_dn(sub: &str) -> String {
+    format!("oidc:{sub}")
+}
+
+#[async_trait]
+impl AuthProvider for OidcClient {
+    /// Users are only authenticated by the provider, see [`OidcClient::start`]
+    async fn authenticate(
+        &self,
+        _login: &str,
+        _password: &SecretString,
+    ) -> Result<Option<AdUserInfo>> {
+        Ok(None)
+    }
+
+    /// Claims from the last login
+    async fn get_user_info(&self, dn: &str) -> Result<Option<AdUserInfo>> {
+        let mut tx = self.registry.begin().await?;
+        let user = tx.get_oidc_user_by_dn(dn).await?;
+        tx.commit().await?;
+        Ok(user.map(|user| AdUserInfo {
+            dn: dn.into(),
+            email: user.email,
+            groups: user.groups,
+        }))
+    }
+}
@@ -1,56 +1,198 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::SecretString;
 use tachikoma::db::{Registry, run_migrations};
+use tachikoma::logic::inventory::{
+    InventoryFormat, InventoryService, inventory_sync_on_sighup, sync_inventory_file,
+};
 use tachikoma::logic::message_senders::DisabledMessageSender;
 use tachikoma::logic::notifications::Notifier;
 use tachikoma::{configuration::get_config, set_env, web::Application};
 use tracing::info;
 
-use ldap3::{LdapConnAsync, drive};
-use secrecy::ExposeSecret;
+use tachikoma::auth::{LocalProvider, build_auth_provider};
+use tachikoma::logic::leader::{DEFAULT_INTERVAL, LeaderElection};
 use tachikoma::logic::release::hosts_release_timer;
+use tachikoma::shutdown::cancel_on_signal;
 use tachikoma::telemetry::init_tracing;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Parser)]
+#[command(version, about = "Tachikoma web server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import or export hosts inventory
+    #[command(subcommand)]
+    Inventory(InventoryCommand),
+    /// Manage users of the local authentication provider
+    #[command(subcommand)]
+    LocalUser(LocalUserCommand),
+}
+
+#[derive(Subcommand)]
+enum LocalUserCommand {
+    /// Create a user or update the existing one, password is read from stdin
+    Set {
+        login: String,
+        #[arg(long)]
+        email: String,
+        /// Groups used for lease limits and group access, can be repeated
+        #[arg(long = "group")]
+        groups: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum InventoryCommand {
+    /// Write all hosts with their groups and tags
+    Export {
+        #[arg(long, default_value = "csv")]
+        format: InventoryFormat,
+        /// Output file, stdout if not set
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Create and update hosts and groups from a file
+    Import {
+        file: PathBuf,
+        /// Inferred from the file extension if not set
+        #[arg(long)]
+        format: Option<InventoryFormat>,
+        /// Only print changes without applying them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
     let settings = get_config()?;
-    init_tracing();
+    let _tracing = init_tracing(&settings)?;
     set_env();
 
     run_migrations(&settings.database).await?;
     let registry = Registry::new(&settings.database).await?;
+
+    match cli.command {
+        Some(Command::Inventory(command)) => {
+            return run_inventory_command(InventoryService::new(registry), command).await;
+        }
+        Some(Command::LocalUser(command)) => {
+            return run_local_user_command(LocalProvider::new(registry), command).await;
+        }
+        None => {}
+    }
+
+    if let Some(inventory) = &settings.inventory {
+        let diff = sync_inventory_file(&InventoryService::new(registry.clone()), inventory).await?;
+        info!("Inventory synced:\n{diff}");
+    }
+
     let notifier = Notifier::new(registry.clone(), DisabledMessageSender {});
 
-    let (ldap_conn, ldap) =
-        LdapConnAsync::with_settings(settings.ldap.clone().into(), &settings.ldap.url).await?;
-    let ldap_conn_task = drive!(ldap_conn);
-    let (authorized_ldap_conn, mut authorized_ldap) =
-        LdapConnAsync::with_settings(settings.ldap.clone().into(), &settings.ldap.url).await?;
-    let authorized_ldap_conn_task = drive!(authorized_ldap_conn);
-    authorized_ldap
-        .simple_bind(&settings.ldap.login, settings.ldap.password.expose_secret())
-        .await?
-        .success()?;
+    // every component stops on the signal, any of them exiting stops the rest
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    let auth_provider = build_auth_provider(&settings, registry.clone(), shutdown.clone()).await?;
 
     let server = Application::build(
         &settings,
         registry.clone(),
-        ldap,
-        authorized_ldap,
+        auth_provider,
         "bot_username".into(),
     )
     .await?;
 
-    tokio::select! {
-        _ = server.serve_forever() => {
-            info!("Server exited")
+    let inventory_sync = async {
+        if let Some(inventory) = settings.inventory.clone() {
+            let service = InventoryService::new(registry.clone());
+            inventory_sync_on_sighup(service, inventory, shutdown.clone()).await;
+            info!("Inventory sync exited");
         }
-        _ = ldap_conn_task => {
-            info!("Ldap connection exited")
+    };
+    let (served, _, _) = tokio::join!(
+        async {
+            let served = server.serve_until(shutdown.clone()).await;
+            info!("Server exited");
+            shutdown.cancel();
+            served
+        },
+        // only one of the instances sharing the database releases hosts and sends reminders
+        async {
+            LeaderElection::new(registry.clone(), DEFAULT_INTERVAL)
+                .run(shutdown.clone(), |leading| {
+                    hosts_release_timer(registry.clone(), &notifier, leading)
+                })
+                .await;
+            info!("Hosts release timer exited");
+        },
+        inventory_sync,
+    );
+    registry.close().await;
+    info!("Server shut down");
+    Ok(served?)
+}
+
+async fn run_inventory_command(
+    service: InventoryService,
+    command: InventoryCommand,
+) -> Result<(), anyhow::Error> {
+    match command {
+        InventoryCommand::Export { format, output } => {
+            let content = format.render(&service.export().await?)?;
+            match output {
+                Some(path) => std::fs::write(&path, content)
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => print!("{content}"),
+            }
         }
-        _ = authorized_ldap_conn_task => {
-            info!("Authorized ldap connection exited")
+        InventoryCommand::Import {
+            file,
+            format,
+            dry_run,
+        } => {
+            let format = format
+                .or_else(|| InventoryFormat::from_path(&file))
+                .with_context(|| format!("Can't infer format of {}", file.display()))?;
+            let content = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let diff = service.import(&format.parse(&content)?, dry_run).await?;
+            println!("{diff}");
+            if dry_run {
+                println!("Dry run, nothing was changed");
+            }
         }
-        _ = hosts_release_timer(registry, &notifier) => {
-            info!("Hosts release timer exited")
+    }
+    Ok(())
+}
+
+async fn run_local_user_command(
+    provider: LocalProvider,
+    command: LocalUserCommand,
+) -> Result<(), anyhow::Error> {
+    match command {
+        LocalUserCommand::Set {
+            login,
+            email,
+            groups,
+        } => {
+            let mut password = String::new();
+            std::io::stdin()
+                .read_line(&mut password)
+                .context("Failed to read password")?;
+            let password = SecretString::from(password.trim_end_matches(['\r', '\n']));
+            provider
+                .set_user(&login, &password, &email, &groups)
+                .await?;
+            println!("User '{login}' is saved");
         }
     }
     Ok(())
@@ -1,29 +1,34 @@
+use std::sync::Arc;
+
 use anyhow::Context;
-use ldap3::LdapConnAsync;
-use secrecy::ExposeSecret;
 use tachikoma::{
-    bot::build_tg_bot,
+    auth::build_auth_provider,
+    bot::{BotHealth, build_tg_bot},
     configuration::get_config,
     db::{Registry, run_migrations},
     logic::{
-        message_senders::TgMessages, notifications::Notifier, release::hosts_release_timer,
+        inventory::{InventoryService, inventory_sync_on_sighup, sync_inventory_file},
+        leader::{DEFAULT_INTERVAL, LeaderElection},
+        message_senders::TgMessages,
+        notifications::Notifier,
+        release::hosts_release_timer,
         users::UsersService,
     },
     set_env,
     web::Application,
 };
 
-use ldap3::drive;
 use teloxide::{Bot, requests::Requester};
-use tokio::select;
+use tokio::join;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
-use tachikoma::telemetry::init_tracing;
+use tachikoma::{shutdown::cancel_on_signal, telemetry::init_tracing};
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let settings = get_config()?;
-    init_tracing();
+    let _tracing = init_tracing(&settings)?;
     set_env();
 
     tracing::info!("Starting tachikama");
@@ -38,47 +43,62 @@ async fn main() -> Result<(), anyhow::Error> {
         .with_context(|| "Bot hasn't username?!")?;
 
     let registry = Registry::new(&settings.database).await?;
+    if let Some(inventory) = &settings.inventory {
+        let diff = sync_inventory_file(&InventoryService::new(registry.clone()), inventory).await?;
+        info!("Inventory synced:\n{diff}");
+    }
     let notifier = Notifier::new(registry.clone(), TgMessages::new(bot.clone()));
 
-    let (ldap_conn, ldap) =
-        LdapConnAsync::with_settings(settings.ldap.clone().into(), &settings.ldap.url).await?;
-    let ldap_conn_task = drive!(ldap_conn);
-    let (authorized_ldap_conn, mut authorized_ldap) =
-        LdapConnAsync::with_settings(settings.ldap.clone().into(), &settings.ldap.url).await?;
-    let authorized_ldap_conn_task = drive!(authorized_ldap_conn);
-    authorized_ldap
-        .simple_bind(&settings.ldap.login, settings.ldap.password.expose_secret())
-        .await?
-        .success()?;
+    // every component stops on the signal, any of them exiting stops the rest
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    let auth_provider = build_auth_provider(&settings, registry.clone(), shutdown.clone()).await?;
 
     let server = Application::build(
         &settings,
         registry.clone(),
-        ldap,
-        authorized_ldap,
+        auth_provider,
         format!("https://t.me/{bot_username}"),
     )
     .await?;
 
+    let bot_health = BotHealth::new(bot.clone());
+    server
+        .health_checks()
+        .add("telegram", Arc::new(bot_health.clone()));
     let mut dispatcher = build_tg_bot(bot, UsersService::new(registry.clone()));
 
-    select! {
-        _ = server.serve_forever() => {
-            info!("Server exited")
-        }
-        _ = ldap_conn_task => {
-            info!("Ldap connection exited")
-        }
-        _ = authorized_ldap_conn_task => {
-            info!("Authorized ldap connection exited")
-        }
-        _ = hosts_release_timer(registry, &notifier) => {
-            info!("Hosts release timer exited")
-        }
-        _ = dispatcher.dispatch() => {
-            info!("Bot exited")
+    let inventory_sync = async {
+        if let Some(inventory) = settings.inventory.clone() {
+            let service = InventoryService::new(registry.clone());
+            inventory_sync_on_sighup(service, inventory, shutdown.clone()).await;
+            info!("Inventory sync exited");
         }
     };
+    let (served, _, _, _) = join!(
+        async {
+            let served = server.serve_until(shutdown.clone()).await;
+            info!("Server exited");
+            shutdown.cancel();
+            served
+        },
+        // only one of the instances sharing the database releases hosts and sends reminders
+        async {
+            LeaderElection::new(registry.clone(), DEFAULT_INTERVAL)
+                .run(shutdown.clone(), |leading| {
+                    hosts_release_timer(registry.clone(), &notifier, leading)
+                })
+                .await;
+            info!("Hosts release timer exited");
+        },
+        async {
+            bot_health.dispatch(&mut dispatcher, shutdown.clone()).await;
+            info!("Bot exited");
+            shutdown.cancel();
+        },
+        inventory_sync,
+    );
+    registry.close().await;
     info!("tachikama shut down");
-    Ok(())
+    Ok(served?)
 }
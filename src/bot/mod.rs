@@ -1,15 +1,25 @@
 pub mod handlers;
 
 use self::handlers::build_handler;
-use crate::logic::users::UsersService;
+use crate::{health::HealthCheck, logic::users::UsersService};
 
-use std::error::Error;
+use std::{
+    error::Error,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::bail;
+use async_trait::async_trait;
 use teloxide::{
     Bot,
     dispatching::{DefaultKey, Dispatcher, dialogue::InMemStorage},
     macros::BotCommands,
     prelude::*,
 };
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone, Default, Debug)]
 pub enum BotState {
@@ -41,6 +51,50 @@ pub fn build_tg_bot(
         .error_handler(LoggingErrorHandler::with_custom_text(
             "An error has occurred in the dispatcher",
         ))
-        .enable_ctrlc_handler()
         .build()
 }
+
+/// Bot is ready while its dispatcher is running and Telegram API is reachable
+#[derive(Clone)]
+pub struct BotHealth {
+    bot: Bot,
+    running: Arc<AtomicBool>,
+}
+
+impl BotHealth {
+    pub fn new(bot: Bot) -> Self {
+        Self {
+            bot,
+            running: Default::default(),
+        }
+    }
+
+    /// Runs the dispatcher until `shutdown` is cancelled, updates being handled are finished
+    pub async fn dispatch(
+        &self,
+        dispatcher: &mut Dispatcher<Bot, Box<dyn Error + Send + Sync>, DefaultKey>,
+        shutdown: CancellationToken,
+    ) {
+        let token = dispatcher.shutdown_token();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            if let Ok(stopped) = token.shutdown() {
+                stopped.await;
+            }
+        });
+        self.running.store(true, Ordering::Relaxed);
+        dispatcher.dispatch().await;
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl HealthCheck for BotHealth {
+    async fn check(&self) -> anyhow::Result<()> {
+        if !self.running.load(Ordering::Relaxed) {
+            bail!("Dispatcher isn't running");
+        }
+        self.bot.get_me().await?;
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 use std::{
     net::{IpAddr, SocketAddr},
     path::PathBuf,
+    time::Duration,
 };
 
+use crate::db::models::Role;
+use crate::ldap::{cache::UserInfoCache, schema::LdapSchema};
 use secrecy::ExposeSecret;
 use secrecy::SecretString;
 use serde::{Deserialize, Deserializer};
@@ -15,8 +18,185 @@ use sqlx::{
 #[derive(Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
-    pub ldap: LdapSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    /// Required for LDAP authentication
+    pub ldap: Option<LdapSettings>,
+    /// Enables login with OpenID Connect alongside `auth` provider
+    pub oidc: Option<OidcSettings>,
     pub app: AppSettings,
+    pub inventory: Option<InventorySettings>,
+    #[serde(default)]
+    pub login_throttle: LoginThrottleSettings,
+    #[serde(default)]
+    pub roles: RolesSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    /// Enables export of traces with OTLP
+    pub telemetry: Option<TelemetrySettings>,
+    /// Enables `/metrics` on a separate address
+    pub metrics: Option<MetricsSettings>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MetricsSettings {
+    pub host: IpAddr,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+}
+
+impl MetricsSettings {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct LogSettings {
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// A JSON object per line with fields of the event and its spans at the top level
+    Json,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TelemetrySettings {
+    /// OTLP/HTTP collector, traces are sent to `{otlp_endpoint}/v1/traces`
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Share of traces started by this service which are exported, traces of callers follow their decision
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+    /// Which spans are exported, same syntax as `RUST_LOG`
+    #[serde(default = "default_telemetry_filter")]
+    pub filter: String,
+}
+
+fn default_service_name() -> String {
+    "tachikoma".into()
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+fn default_telemetry_filter() -> String {
+    "info,tachikoma=debug".into()
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RolesSettings {
+    /// Role of users who aren't members of any group listed below
+    pub default_role: Role,
+    pub admin_groups: Vec<String>,
+    pub user_groups: Vec<String>,
+    pub viewer_groups: Vec<String>,
+}
+
+impl Default for RolesSettings {
+    fn default() -> Self {
+        Self {
+            default_role: Role::User,
+            admin_groups: vec![],
+            user_groups: vec![],
+            viewer_groups: vec![],
+        }
+    }
+}
+
+impl RolesSettings {
+    /// The most privileged role among the groups, `default_role` if none of them is listed
+    pub fn role_of(&self, groups: &[String]) -> Role {
+        [
+            (Role::Admin, &self.admin_groups),
+            (Role::User, &self.user_groups),
+            (Role::Viewer, &self.viewer_groups),
+        ]
+        .into_iter()
+        .find(|(_, listed)| groups.iter().any(|g| listed.contains(g)))
+        .map_or(self.default_role, |(role, _)| role)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LoginThrottleSettings {
+    /// Failed attempts allowed before lockout, counted by username and by client address
+    pub max_failures: i32,
+    /// Failures are forgotten after this long without new ones
+    pub window_secs: i64,
+    /// First lockout period, doubled with every failure after it
+    pub lockout_secs: i64,
+    pub max_lockout_secs: i64,
+    /// Take client address from `X-Forwarded-For` set by a reverse proxy
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for LoginThrottleSettings {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            window_secs: 3600,
+            lockout_secs: 30,
+            max_lockout_secs: 3600,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct InventorySettings {
+    /// CSV, YAML or TOML file, format is inferred from the extension
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum AuthSettings {
+    /// Users and their groups are taken from AD
+    #[default]
+    Ldap,
+    /// Users from `local_users` table
+    Local,
+    /// Single user which can log in with any password, for development only
+    Static {
+        login: String,
+        email: String,
+        #[serde(default)]
+        groups: Vec<String>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct OidcSettings {
+    /// Discovery document is fetched from `{issuer_url}/.well-known/openid-configuration`
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: SecretString,
+    /// Must point to `/login/oidc/callback` of this server
+    pub redirect_url: String,
+    /// Claim with the list of groups used for lease limits and group access
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_groups_claim() -> String {
+    "groups".into()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".into(), "email".into(), "profile".into()]
 }
 
 #[derive(Deserialize, Clone)]
@@ -27,6 +207,46 @@ pub struct LdapSettings {
     pub login: String,
     pub password: SecretString,
     pub users_query: String,
+    /// Filters and attributes used to look up users, defaults match AD
+    #[serde(default)]
+    pub schema: LdapSchema,
+    /// Maximum number of idle connections kept open
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// Maximum number of open connections, including ones checking passwords
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// How long user info fetched from AD is cached
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// How long expired user info is still used while AD is unavailable
+    #[serde(default = "default_cache_max_stale_secs")]
+    pub cache_max_stale_secs: u64,
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_max_connections() -> usize {
+    16
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_cache_max_stale_secs() -> u64 {
+    3600
+}
+
+impl LdapSettings {
+    pub fn user_info_cache(&self) -> UserInfoCache {
+        UserInfoCache::new(
+            Duration::from_secs(self.cache_ttl_secs),
+            Duration::from_secs(self.cache_max_stale_secs),
+        )
+    }
 }
 
 impl From<LdapSettings> for ldap3::LdapConnSettings {
@@ -46,6 +266,17 @@ pub struct AppSettings {
     pub lease_limit: usize,
     #[serde(deserialize_with = "deserialize_key_secret")]
     pub hmac_secret: Vec<u8>,
+    #[serde(default)]
+    pub session_store: SessionStoreKind,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    /// Sessions are lost on restart and aren't shared between replicas
+    Memory,
+    #[default]
+    Postgres,
 }
 
 impl AppSettings {
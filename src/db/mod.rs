@@ -1,14 +1,25 @@
 pub mod models;
+pub mod sessions;
 
 use std::ops::Deref;
 
+use chrono::TimeDelta;
 use chrono::prelude::*;
-use models::{AdGroupLeaseLimit, Group, GroupId};
-use sqlx::{PgPool, Postgres, Transaction};
+use models::{
+    AdGroupLeaseLimit, Group, GroupAccess, GroupHostsCount, GroupId, GroupLeasePolicy, LeaseNote,
+    LeaseRecord, LocalUser, OidcUser, Role,
+};
+use sqlx::postgres::PgListener;
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::{Connection, PgConnection, PgPool, Postgres, Transaction};
+use tracing::instrument;
 
 use crate::configuration::DatabaseSettings;
 use crate::db::models::{Host, HostId, LeasedHost, User, UserId};
 
+/// Notified by a trigger when leases of hosts change, [`HostsEvent`](crate::logic::events::HostsEvent) is sent there too
+pub const LEASES_CHANNEL: &str = "leases_changed";
+
 #[derive(Clone)]
 pub struct Registry {
     pool: PgPool,
@@ -19,67 +30,168 @@ impl Registry {
         let pool = PgPool::connect_with(settings.with_db()).await?;
         Ok(Registry { pool })
     }
-    pub async fn begin(&self) -> sqlx::Result<RegistryTx> {
+    pub async fn ping(&self) -> sqlx::Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+    /// Dedicated connection for the advisory lock `key`.
+    /// The connection is detached from the pool, so the lock can't leak with it.
+    pub async fn advisory_lock(&self, key: i64) -> sqlx::Result<AdvisoryLock> {
+        Ok(AdvisoryLock {
+            conn: self.pool.acquire().await?.detach(),
+            key,
+        })
+    }
+    /// Listens for notifications on `channel` with a dedicated connection
+    pub async fn listen(&self, channel: &str) -> sqlx::Result<PgListener> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(channel).await?;
+        Ok(listener)
+    }
+    /// Waits for connections in use to be returned and closes all of them
+    pub async fn close(&self) {
+        self.pool.close().await
+    }
+    pub async fn begin(&self) -> sqlx::Result<RegistryTx<'_>> {
         Ok(RegistryTx {
             tx: self.pool.begin().await?,
         })
     }
 }
+/// Session-level advisory lock, it's held by a dedicated connection until released or the connection is lost
+pub struct AdvisoryLock {
+    conn: PgConnection,
+    key: i64,
+}
+
+impl AdvisoryLock {
+    /// Takes the lock if no one else holds it
+    pub async fn try_acquire(&mut self) -> sqlx::Result<bool> {
+        sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(self.key)
+            .fetch_one(&mut self.conn)
+            .await
+    }
+
+    /// Fails if the connection holding the lock is lost
+    pub async fn check(&mut self) -> sqlx::Result<()> {
+        sqlx::query("SELECT 1").execute(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Releases the lock, the connection is kept to take it again
+    pub async fn release(&mut self) -> sqlx::Result<()> {
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(self.key)
+            .execute(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn close(self) -> sqlx::Result<()> {
+        self.conn.close().await
+    }
+}
+
 pub struct RegistryTx<'c> {
     tx: Transaction<'c, Postgres>,
 }
 
 impl RegistryTx<'_> {
+    #[instrument(level = "debug", skip_all)]
     pub async fn commit(self) -> sqlx::Result<()> {
         self.tx.commit().await
     }
 
+    /// Notification is delivered to listeners of `channel` once the transaction is committed
+    #[instrument(level = "debug", skip_all)]
+    pub async fn notify(&mut self, channel: &str, payload: &str) -> sqlx::Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(channel)
+            .bind(payload)
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_all_hosts(&mut self) -> sqlx::Result<Vec<Host>> {
+        sqlx::query_as("SELECT * FROM hosts WHERE retired_at IS NULL OR user_id IS NOT NULL ORDER BY hosts.ip_address ASC")
+            .fetch_all(&mut *self.tx)
+            .await
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_inventory_hosts(&mut self) -> sqlx::Result<Vec<Host>> {
         sqlx::query_as("SELECT * FROM hosts ORDER BY hosts.ip_address ASC")
             .fetch_all(&mut *self.tx)
             .await
     }
 
+    /// Hosts of every group which has any, leased retired hosts are counted as leased
+    #[instrument(level = "debug", skip_all)]
+    pub async fn count_hosts_by_group(&mut self) -> sqlx::Result<Vec<GroupHostsCount>> {
+        sqlx::query_as(
+            r#"
+            SELECT group_id,
+                count(*) FILTER (WHERE user_id IS NULL AND retired_at IS NULL) AS free,
+                count(*) FILTER (WHERE user_id IS NOT NULL) AS leased,
+                count(*) FILTER (WHERE user_id IS NULL AND retired_at IS NOT NULL) AS retired,
+                count(DISTINCT user_id) AS users
+            FROM hosts GROUP BY group_id
+            "#,
+        )
+        .fetch_all(&mut *self.tx)
+        .await
+    }
+
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_available_group_hosts(
         &mut self,
         group_id: &GroupId,
     ) -> sqlx::Result<Vec<Host>> {
-        sqlx::query_as("SELECT * FROM hosts WHERE user_id is NULL AND group_id = $1 ORDER BY hosts.ip_address ASC")
-            .bind(group_id)
-            .fetch_all(&mut *self.tx)
-            .await
+        sqlx::query_as(
+            r#"
+            WITH RECURSIVE subgroups AS (
+                SELECT id FROM groups WHERE id = $1
+                UNION
+                SELECT groups.id FROM groups JOIN subgroups ON groups.parent_id = subgroups.id
+            )
+            SELECT * FROM hosts
+            WHERE user_id is NULL AND retired_at IS NULL AND group_id IN (SELECT id FROM subgroups)
+            ORDER BY hosts.ip_address ASC
+            "#,
+        )
+        .bind(group_id)
+        .fetch_all(&mut *self.tx)
+        .await
     }
 
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_available_hosts(&mut self) -> sqlx::Result<Vec<Host>> {
-        sqlx::query_as("SELECT * FROM hosts WHERE user_id is NULL ORDER BY hosts.ip_address ASC")
+        sqlx::query_as("SELECT * FROM hosts WHERE user_id is NULL AND retired_at IS NULL ORDER BY hosts.ip_address ASC")
             .fetch_all(&mut *self.tx)
             .await
     }
-    pub async fn get_first_available_group_host(
-        &mut self,
-        group_id: &GroupId,
-    ) -> sqlx::Result<Option<Host>> {
-        sqlx::query_as("SELECT * FROM hosts WHERE user_id is NULL AND group_id = $1 LIMIT 1")
-            .bind(group_id)
-            .fetch_optional(&mut *self.tx)
-            .await
-    }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_host(&mut self, host_id: &HostId) -> sqlx::Result<Host> {
         sqlx::query_as("SELECT * FROM hosts WHERE id = $1 LIMIT 1")
             .bind(host_id.deref())
             .fetch_one(&mut *self.tx)
             .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_hosts(&mut self, hosts_ids: &[HostId]) -> sqlx::Result<Vec<Host>> {
         sqlx::query_as("SELECT * FROM hosts WHERE id = any($1)")
             .bind(hosts_ids)
             .fetch_all(&mut *self.tx)
             .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_leased_host(&mut self, host_id: &HostId) -> sqlx::Result<LeasedHost> {
         sqlx::query_as(
             r#"
-            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, users.id, users.dn, users.tg_handle, users.email, users.link 
+            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, hosts.lease_note, hosts.lease_ticket, users.id, users.dn, users.tg_handle, users.email, users.link 
             FROM hosts JOIN users on hosts.user_id = users.id 
             WHERE hosts.id = $1
             "#,
@@ -87,10 +199,11 @@ impl RegistryTx<'_> {
         .fetch_one(&mut *self.tx)
         .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_leased_hosts(&mut self, user_id: &UserId) -> sqlx::Result<Vec<LeasedHost>> {
         sqlx::query_as(
             r#"
-            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, users.id, users.dn, users.tg_handle, users.email, users.link 
+            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, hosts.lease_note, hosts.lease_ticket, users.id, users.dn, users.tg_handle, users.email, users.link 
             FROM hosts JOIN users on hosts.user_id = users.id 
             WHERE hosts.user_id = $1 ORDER BY hosts.leased_until, hosts.ip_address ASC
             "#,
@@ -98,18 +211,33 @@ impl RegistryTx<'_> {
         .fetch_all(&mut *self.tx)
         .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_leased_until_hosts(
         &mut self,
         until: DateTime<Utc>,
     ) -> sqlx::Result<Vec<LeasedHost>> {
         sqlx::query_as(
             r#"
-            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, users.id, users.dn, users.tg_handle, users.email, users.link  
+            SELECT hosts.id as hid, hosts.hostname, hosts.ip_address, hosts.leased_until, hosts.group_id, hosts.lease_note, hosts.lease_ticket, users.id, users.dn, users.tg_handle, users.email, users.link  
             FROM hosts JOIN users on hosts.user_id = users.id
             WHERE hosts.leased_until < $1
             "#,
         ).bind(until).fetch_all(&mut *self.tx).await
     }
+    /// Earliest end of a lease at or after `after`
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_next_lease_end(
+        &mut self,
+        after: DateTime<Utc>,
+    ) -> sqlx::Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar(
+            "SELECT MIN(leased_until) FROM hosts WHERE user_id IS NOT NULL AND leased_until >= $1",
+        )
+        .bind(after)
+        .fetch_one(&mut *self.tx)
+        .await
+    }
+    #[instrument(level = "debug", skip_all)]
     pub async fn lease_hosts(
         &mut self,
         user_id: &UserId,
@@ -119,69 +247,176 @@ impl RegistryTx<'_> {
         let ids: Vec<_> = hosts_ids.iter().map(|h| h.0).collect();
 
         sqlx::query!(
-            "UPDATE hosts SET user_id = $1, leased_until = $2 WHERE id = any($3)",
+            "UPDATE hosts SET user_id = $1, leased_until = $2, lease_note = NULL, lease_ticket = NULL WHERE id = any($3)",
             user_id.deref(),
             untill,
             ids.as_slice(),
         )
         .execute(&mut *self.tx)
         .await?;
+
+        self.close_leases(&ids, None).await?;
+        sqlx::query(
+            r#"
+            INSERT INTO lease_history (host_id, user_id, leased_at, leased_until)
+            SELECT host_id, $2, now(), $3 FROM unnest($1::INTEGER[]) AS host_id
+            "#,
+        )
+        .bind(&ids)
+        .bind(user_id.deref())
+        .bind(untill)
+        .execute(&mut *self.tx)
+        .await?;
         Ok(())
     }
+    /// Sets the note of hosts leased by the user and of their open leases in `lease_history`,
+    /// returns hosts which were updated
+    #[instrument(level = "debug", skip_all)]
+    pub async fn set_lease_note(
+        &mut self,
+        user_id: &UserId,
+        hosts_ids: &[HostId],
+        note: &LeaseNote,
+    ) -> sqlx::Result<Vec<HostId>> {
+        let ids: Vec<i32> = sqlx::query_scalar(
+            r#"
+            UPDATE hosts SET lease_note = $3, lease_ticket = $4
+            WHERE id = any($1) AND user_id = $2
+            RETURNING id
+            "#,
+        )
+        .bind(hosts_ids)
+        .bind(user_id.deref())
+        .bind(&note.note)
+        .bind(&note.ticket)
+        .fetch_all(&mut *self.tx)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE lease_history SET lease_note = $3, lease_ticket = $4
+            WHERE host_id = any($1) AND user_id = $2 AND released_at IS NULL
+            "#,
+        )
+        .bind(&ids)
+        .bind(user_id.deref())
+        .bind(&note.note)
+        .bind(&note.ticket)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(ids.into_iter().map(HostId).collect())
+    }
+    /// Marks open leases of the hosts in `lease_history` as released, expired ones at their end
+    async fn close_leases(
+        &mut self,
+        hosts_ids: &[i32],
+        user_id: Option<&UserId>,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE lease_history SET released_at = LEAST(now(), leased_until)
+            WHERE host_id = any($1) AND released_at IS NULL AND ($2::INTEGER IS NULL OR user_id = $2)
+            "#,
+        )
+        .bind(hosts_ids)
+        .bind(user_id.map(|id| id.0))
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+    /// Frees hosts leased by the user, returns the freed ones
+    #[instrument(level = "debug", skip_all)]
     pub async fn free_hosts_for_user(
         &mut self,
         hosts_ids: &[HostId],
         user_id: &UserId,
-    ) -> sqlx::Result<()> {
+    ) -> sqlx::Result<Vec<HostId>> {
         let ids: Vec<_> = hosts_ids.iter().map(|h| h.0).collect();
-        sqlx::query!(
-            "UPDATE hosts SET user_id = NULL, leased_until = NULL WHERE id = any($1) AND user_id = $2",
+        let ids = sqlx::query_scalar!(
+            "UPDATE hosts SET user_id = NULL, leased_until = NULL, lease_note = NULL, lease_ticket = NULL WHERE id = any($1) AND user_id = $2 RETURNING id",
             ids.as_slice(),
             user_id.deref(),
         )
-        .execute(&mut *self.tx)
+        .fetch_all(&mut *self.tx)
         .await?;
-        Ok(())
+        self.close_leases(&ids, Some(user_id)).await?;
+        Ok(ids.into_iter().map(HostId).collect())
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn free_hosts(&mut self, hosts_ids: &[HostId]) -> sqlx::Result<()> {
         let ids: Vec<_> = hosts_ids.iter().map(|h| h.0).collect();
         sqlx::query!(
-            "UPDATE hosts SET user_id = NULL, leased_until = NULL WHERE id = any($1)",
+            "UPDATE hosts SET user_id = NULL, leased_until = NULL, lease_note = NULL, lease_ticket = NULL WHERE id = any($1)",
             ids.as_slice(),
         )
         .execute(&mut *self.tx)
         .await?;
-        Ok(())
+        self.close_leases(&ids, None).await
     }
-    pub async fn free_all(&mut self, user_id: &UserId) -> sqlx::Result<()> {
-        sqlx::query!(
-            "UPDATE hosts SET user_id = NULL, leased_until = NULL WHERE user_id = $1",
-            user_id.deref()
+    #[instrument(level = "debug", skip_all)]
+    pub async fn free_all(&mut self, user_id: &UserId) -> sqlx::Result<Vec<HostId>> {
+        let ids = sqlx::query_scalar!(
+            "UPDATE hosts SET user_id = NULL, leased_until = NULL, lease_note = NULL, lease_ticket = NULL WHERE user_id = $1 RETURNING id",
+            user_id.deref(),
         )
-        .execute(&mut *self.tx)
+        .fetch_all(&mut *self.tx)
         .await?;
-        Ok(())
+        self.close_leases(&ids, Some(user_id)).await?;
+        Ok(ids.into_iter().map(HostId).collect())
     }
+    /// Leases which overlap with `from..to`, ordered by start
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_lease_history(
+        &mut self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<LeaseRecord>> {
+        sqlx::query_as(
+            r#"
+            SELECT lease_history.id, lease_history.host_id, hosts.hostname, hosts.ip_address, hosts.group_id,
+                lease_history.user_id, users.email, lease_history.leased_at, lease_history.leased_until, lease_history.released_at,
+                lease_history.lease_note, lease_history.lease_ticket
+            FROM lease_history
+            JOIN hosts ON hosts.id = lease_history.host_id
+            JOIN users ON users.id = lease_history.user_id
+            WHERE lease_history.leased_at < $2 AND (lease_history.released_at IS NULL OR lease_history.released_at > $1)
+            ORDER BY lease_history.leased_at, lease_history.id
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&mut *self.tx)
+        .await
+    }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_user_by_id(&mut self, user_id: &UserId) -> sqlx::Result<Option<User>> {
         sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id.deref())
             .fetch_optional(&mut *self.tx)
             .await
     }
+    /// The oldest user with the email, which is compared case-insensitively
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_user_by_mail(&mut self, mail: &str) -> sqlx::Result<Option<User>> {
-        sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", mail)
-            .fetch_optional(&mut *self.tx)
-            .await
+        sqlx::query_as!(
+            User,
+            "SELECT * FROM users WHERE lower(email) = lower($1) ORDER BY id LIMIT 1",
+            mail
+        )
+        .fetch_optional(&mut *self.tx)
+        .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_user_by_dn(&mut self, dn: &str) -> sqlx::Result<Option<User>> {
         sqlx::query_as!(User, "SELECT * FROM users WHERE dn = $1", dn)
             .fetch_optional(&mut *self.tx)
             .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_user_by_link(&mut self, link: &str) -> sqlx::Result<Option<User>> {
         sqlx::query_as!(User, "SELECT * FROM users WHERE link = $1", link)
             .fetch_optional(&mut *self.tx)
             .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn set_user_tg_handle(
         &mut self,
         user_id: &UserId,
@@ -196,6 +431,164 @@ impl RegistryTx<'_> {
         .await?;
         Ok(())
     }
+    /// Row is only rewritten when the role has changed
+    #[instrument(level = "debug", skip_all)]
+    pub async fn set_user_role(&mut self, user_id: &UserId, role: Role) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_roles (user_id, role) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET role = EXCLUDED.role
+            WHERE user_roles.role <> EXCLUDED.role
+            "#,
+        )
+        .bind(user_id.deref())
+        .bind(role)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_local_user(&mut self, login: &str) -> sqlx::Result<Option<LocalUser>> {
+        sqlx::query_as("SELECT * FROM local_users WHERE login = $1")
+            .bind(login)
+            .fetch_optional(&mut *self.tx)
+            .await
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn set_local_user(
+        &mut self,
+        login: &str,
+        password_hash: &str,
+        email: &str,
+        groups: &[String],
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO local_users (login, password_hash, email, groups) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (login) DO UPDATE
+            SET password_hash = EXCLUDED.password_hash, email = EXCLUDED.email, groups = EXCLUDED.groups
+            "#,
+        )
+        .bind(login)
+        .bind(password_hash)
+        .bind(email)
+        .bind(groups)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_oidc_user(&mut self, sub: &str) -> sqlx::Result<Option<OidcUser>> {
+        sqlx::query_as("SELECT sub, user_id, email, groups FROM oidc_users WHERE sub = $1")
+            .bind(sub)
+            .fetch_optional(&mut *self.tx)
+            .await
+    }
+    /// The latest OIDC login of the user
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_oidc_user_by_dn(&mut self, dn: &str) -> sqlx::Result<Option<OidcUser>> {
+        sqlx::query_as(
+            r#"
+            SELECT sub, user_id, oidc_users.email, groups FROM oidc_users
+            JOIN users ON users.id = oidc_users.user_id
+            WHERE users.dn = $1
+            ORDER BY updated_at DESC LIMIT 1
+            "#,
+        )
+        .bind(dn)
+        .fetch_optional(&mut *self.tx)
+        .await
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn set_oidc_user(
+        &mut self,
+        sub: &str,
+        user_id: &UserId,
+        email: &str,
+        groups: &[String],
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO oidc_users (sub, user_id, email, groups) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (sub) DO UPDATE
+            SET email = EXCLUDED.email, groups = EXCLUDED.groups, updated_at = now()
+            "#,
+        )
+        .bind(sub)
+        .bind(user_id.deref())
+        .bind(email)
+        .bind(groups)
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_login_lock(
+        &mut self,
+        kind: &str,
+        value: &str,
+    ) -> sqlx::Result<Option<DateTime<Utc>>> {
+        let lock: Option<(Option<DateTime<Utc>>,)> = sqlx::query_as(
+            "SELECT locked_until FROM login_failures WHERE kind = $1 AND value = $2",
+        )
+        .bind(kind)
+        .bind(value)
+        .fetch_optional(&mut *self.tx)
+        .await?;
+        Ok(lock.and_then(|(until,)| until))
+    }
+    /// Failures older than `window` are forgotten, returns number of failures in a row
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_login_failure(
+        &mut self,
+        kind: &str,
+        value: &str,
+        window: TimeDelta,
+    ) -> sqlx::Result<i32> {
+        let (failures,): (i32,) = sqlx::query_as(
+            r#"
+            INSERT INTO login_failures (kind, value, failures, last_failure_at) VALUES ($1, $2, 1, now())
+            ON CONFLICT (kind, value) DO UPDATE
+            SET failures = CASE
+                    WHEN login_failures.last_failure_at < now() - $3 THEN 1
+                    ELSE login_failures.failures + 1
+                END,
+                last_failure_at = now()
+            RETURNING failures
+            "#,
+        )
+        .bind(kind)
+        .bind(value)
+        .bind(window)
+        .fetch_one(&mut *self.tx)
+        .await?;
+        Ok(failures)
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn set_login_lock(
+        &mut self,
+        kind: &str,
+        value: &str,
+        until: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        sqlx::query("UPDATE login_failures SET locked_until = $3 WHERE kind = $1 AND value = $2")
+            .bind(kind)
+            .bind(value)
+            .bind(until)
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn clear_login_failures(&mut self, kind: &str, value: &str) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM login_failures WHERE kind = $1 AND value = $2")
+            .bind(kind)
+            .bind(value)
+            .execute(&mut *self.tx)
+            .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
     pub async fn add_user(
         &mut self,
         dn: &str,
@@ -213,16 +606,113 @@ impl RegistryTx<'_> {
         Ok(rec.id.into())
     }
 
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_host(
+        &mut self,
+        hostname: &str,
+        ip_address: IpNetwork,
+        group_id: &GroupId,
+        tags: &[String],
+    ) -> sqlx::Result<HostId> {
+        let rec = sqlx::query!(
+            "INSERT INTO hosts (hostname, ip_address, group_id, tags) VALUES ($1, $2, $3, $4) RETURNING id",
+            hostname,
+            ip_address,
+            group_id.deref(),
+            tags,
+        )
+        .fetch_one(&mut *self.tx)
+        .await?;
+        Ok(rec.id.into())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn update_host(
+        &mut self,
+        host_id: &HostId,
+        ip_address: IpNetwork,
+        group_id: &GroupId,
+        tags: &[String],
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            "UPDATE hosts SET ip_address = $1, group_id = $2, tags = $3, retired_at = NULL WHERE id = $4",
+            ip_address,
+            group_id.deref(),
+            tags,
+            host_id.deref(),
+        )
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn retire_hosts(&mut self, hosts_ids: &[HostId]) -> sqlx::Result<()> {
+        let ids: Vec<_> = hosts_ids.iter().map(|h| h.0).collect();
+        sqlx::query!(
+            "UPDATE hosts SET retired_at = now() WHERE id = any($1) AND retired_at IS NULL",
+            ids.as_slice(),
+        )
+        .execute(&mut *self.tx)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_groups(&mut self) -> sqlx::Result<Vec<Group>> {
         sqlx::query_as("SELECT * FROM groups ORDER BY name ASC")
             .fetch_all(&mut *self.tx)
             .await
     }
+    /// Group without a parent is put under [`GroupId::ROOT`]
+    #[instrument(level = "debug", skip_all)]
+    pub async fn add_group(
+        &mut self,
+        name: &str,
+        parent_id: Option<&GroupId>,
+    ) -> sqlx::Result<GroupId> {
+        let rec = sqlx::query!(
+            "INSERT INTO groups (name, parent_id) VALUES ($1, $2) RETURNING id",
+            name,
+            parent_id.unwrap_or(&GroupId::ROOT).0,
+        )
+        .fetch_one(&mut *self.tx)
+        .await?;
+        Ok(rec.id.into())
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_group_lease_policies(&mut self) -> sqlx::Result<Vec<GroupLeasePolicy>> {
+        sqlx::query_as("SELECT * FROM group_lease_policies")
+            .fetch_all(&mut *self.tx)
+            .await
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn get_groups_access(&mut self) -> sqlx::Result<Vec<GroupAccess>> {
+        sqlx::query_as("SELECT * FROM group_access")
+            .fetch_all(&mut *self.tx)
+            .await
+    }
+    #[instrument(level = "debug", skip_all)]
+    pub async fn count_user_leases_in_groups(
+        &mut self,
+        user_id: &UserId,
+        groups_ids: &[GroupId],
+    ) -> sqlx::Result<i64> {
+        let ids: Vec<_> = groups_ids.iter().map(|g| g.0).collect();
+        let rec = sqlx::query!(
+            "SELECT count(*) as count FROM hosts WHERE user_id = $1 AND group_id = any($2)",
+            user_id.deref(),
+            ids.as_slice(),
+        )
+        .fetch_one(&mut *self.tx)
+        .await?;
+        Ok(rec.count.unwrap_or(0))
+    }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_all_users(&mut self) -> sqlx::Result<Vec<User>> {
         sqlx::query_as("SELECT * from users")
             .fetch_all(&mut *self.tx)
             .await
     }
+    #[instrument(level = "debug", skip_all)]
     pub async fn get_ad_groups_lease_limits(
         &mut self,
         groups: &Vec<String>,
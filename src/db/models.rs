@@ -57,6 +57,10 @@ impl From<i32> for HostId {
 #[derive(sqlx::Type, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[sqlx(transparent)]
 pub struct GroupId(pub i32);
+impl GroupId {
+    /// Top of the groups tree, parent of every group created without one
+    pub const ROOT: GroupId = GroupId(0);
+}
 impl Display for GroupId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -95,6 +99,24 @@ pub struct LeasedHost {
     pub leased_until: DateTime<Utc>,
     #[sqlx(flatten)]
     pub user: User,
+    #[sqlx(flatten)]
+    pub note: LeaseNote,
+}
+
+/// Why hosts are leased, set by the user on lease and editable until release
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, FromRow)]
+pub struct LeaseNote {
+    #[sqlx(rename = "lease_note")]
+    pub note: Option<String>,
+    /// Link to the ticket the hosts are used for
+    #[sqlx(rename = "lease_ticket")]
+    pub ticket: Option<String>,
+}
+
+impl LeaseNote {
+    pub fn is_empty(&self) -> bool {
+        self.note.is_none() && self.ticket.is_none()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
@@ -105,12 +127,17 @@ pub struct Host {
     pub leased_until: Option<DateTime<Utc>>,
     pub user_id: Option<UserId>,
     pub group_id: GroupId,
+    pub tags: Vec<String>,
+    pub retired_at: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    pub lease_note: LeaseNote,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
 pub struct Group {
     pub id: GroupId,
     pub name: String,
+    pub parent_id: Option<GroupId>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
@@ -122,8 +149,93 @@ pub struct User {
     pub link: String,
 }
 
+/// Hosts of a group by state
+#[derive(Clone, Debug, FromRow)]
+pub struct GroupHostsCount {
+    pub group_id: GroupId,
+    pub free: i64,
+    pub leased: i64,
+    pub retired: i64,
+    /// Users holding hosts of the group
+    pub users: i64,
+}
+
+/// Lease from `lease_history` with the host and the user
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct LeaseRecord {
+    pub id: i32,
+    pub host_id: HostId,
+    pub hostname: String,
+    pub ip_address: IpNetwork,
+    pub group_id: GroupId,
+    pub user_id: UserId,
+    pub email: String,
+    pub leased_at: DateTime<Utc>,
+    pub leased_until: DateTime<Utc>,
+    /// `None` while the host is still leased
+    pub released_at: Option<DateTime<Utc>>,
+    #[sqlx(flatten)]
+    pub note: LeaseNote,
+}
+
+/// Roles are ordered by privileges, every role has all rights of the lower ones
+#[derive(
+    sqlx::Type, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    /// Can only see hosts
+    Viewer,
+    /// Can lease and release hosts
+    User,
+    /// Can also manage inventory
+    Admin,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::User => "user",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
 pub struct AdGroupLeaseLimit {
     pub group: String,
     pub limit: i32,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct GroupLeasePolicy {
+    pub group_id: GroupId,
+    pub max_lease_hours: Option<i32>,
+    pub default_lease_hours: Option<i32>,
+    pub user_limit: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow)]
+pub struct GroupAccess {
+    pub group_id: GroupId,
+    pub ad_group: String,
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct LocalUser {
+    pub login: String,
+    pub password_hash: String,
+    pub email: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct OidcUser {
+    pub sub: String,
+    pub user_id: UserId,
+    pub email: String,
+    pub groups: Vec<String>,
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use chrono::DateTime;
+use sqlx::{PgPool, types::Json};
+use tower_sessions::{
+    ExpiredDeletion, SessionStore,
+    session::{Id, Record},
+    session_store,
+};
+
+use super::Registry;
+
+/// Keeps sessions in `sessions` table, so they survive restarts and are shared between replicas
+#[derive(Clone, Debug)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            pool: registry.pool.clone(),
+        }
+    }
+}
+
+fn backend_error(err: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(err.to_string())
+}
+
+fn expiry_date(record: &Record) -> session_store::Result<DateTime<chrono::Utc>> {
+    DateTime::from_timestamp(
+        record.expiry_date.unix_timestamp(),
+        record.expiry_date.nanosecond(),
+    )
+    .ok_or_else(|| session_store::Error::Encode("Session expiry date is out of range".into()))
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            let inserted = sqlx::query(
+                r#"
+                INSERT INTO sessions (id, record, expiry_date) VALUES ($1, $2, $3)
+                ON CONFLICT (id) DO NOTHING
+                "#,
+            )
+            .bind(record.id.to_string())
+            .bind(Json(&*record))
+            .bind(expiry_date(record)?)
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+            if inserted.rows_affected() > 0 {
+                return Ok(());
+            }
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, record, expiry_date) VALUES ($1, $2, $3)
+            ON CONFLICT (id) DO UPDATE SET record = EXCLUDED.record, expiry_date = EXCLUDED.expiry_date
+            "#,
+        )
+        .bind(record.id.to_string())
+        .bind(Json(record))
+        .bind(expiry_date(record)?)
+        .execute(&self.pool)
+        .await
+        .map_err(backend_error)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let record: Option<(Json<Record>,)> =
+            sqlx::query_as("SELECT record FROM sessions WHERE id = $1 AND expiry_date > now()")
+                .bind(session_id.to_string())
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(backend_error)?;
+        Ok(record.map(|(Json(record),)| record))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE id = $1")
+            .bind(session_id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for PgSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        sqlx::query("DELETE FROM sessions WHERE expiry_date <= now()")
+            .execute(&self.pool)
+            .await
+            .map_err(backend_error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::db::Registry;
+
+/// Checks fail if they don't finish in time, e.g. when AD doesn't respond
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Results are reused for this long, so frequent probes don't load AD and Telegram API
+pub const READINESS_TTL: Duration = Duration::from_secs(10);
+
+/// Dependency the application needs to serve requests
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    async fn check(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl HealthCheck for Registry {
+    async fn check(&self) -> Result<()> {
+        Ok(self.ping().await?)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Fail,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckResult {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u128,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Readiness {
+    pub status: Status,
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+type NamedCheck = (String, Arc<dyn HealthCheck>);
+
+/// Readiness with the time it was checked and the number of checks it covers
+type CachedReadiness = (Instant, usize, Readiness);
+
+/// Named checks of all dependencies, components started later (e.g. Telegram bot) can add their own
+#[derive(Clone, Default)]
+pub struct HealthChecks {
+    checks: Arc<RwLock<Vec<NamedCheck>>>,
+    /// Locked while checks run, so concurrent callers wait for the same result
+    last: Arc<Mutex<Option<CachedReadiness>>>,
+}
+
+impl HealthChecks {
+    pub fn add(&self, name: &str, check: Arc<dyn HealthCheck>) {
+        self.checks.write().unwrap().push((name.into(), check));
+    }
+
+    /// Runs all checks concurrently, ready only if every one of them passes.
+    /// Result is reused for [`READINESS_TTL`] unless checks were added.
+    pub async fn readiness(&self) -> Readiness {
+        let mut last = self.last.lock().await;
+        let count = self.checks.read().unwrap().len();
+        if let Some((checked_at, checked, readiness)) = last.as_ref()
+            && checked_at.elapsed() < READINESS_TTL
+            && *checked == count
+        {
+            return readiness.clone();
+        }
+        let readiness = self.run_checks().await;
+        *last = Some((Instant::now(), count, readiness.clone()));
+        readiness
+    }
+
+    async fn run_checks(&self) -> Readiness {
+        let mut tasks = JoinSet::new();
+        for (name, check) in self.checks.read().unwrap().iter().cloned() {
+            tasks.spawn(async move {
+                let started = Instant::now();
+                let result = match tokio::time::timeout(CHECK_TIMEOUT, check.check()).await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(err)) => Some(format!("{err:#}")),
+                    Err(_) => Some(format!("timed out after {CHECK_TIMEOUT:?}")),
+                };
+                let result = CheckResult {
+                    status: if result.is_none() {
+                        Status::Ok
+                    } else {
+                        Status::Fail
+                    },
+                    error: result,
+                    duration_ms: started.elapsed().as_millis(),
+                };
+                (name, result)
+            });
+        }
+        let checks: BTreeMap<_, _> = tasks.join_all().await.into_iter().collect();
+        let status = if checks.values().all(|c| c.status == Status::Ok) {
+            Status::Ok
+        } else {
+            Status::Fail
+        };
+        Readiness { status, checks }
+    }
+}
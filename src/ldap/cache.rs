@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::AdUserInfo;
+use crate::metrics::METRICS;
+
+/// Counters of cache lookups since start
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Expired entries returned because AD was unavailable
+    pub stale_hits: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses + self.stale_hits;
+        if total == 0 {
+            return 0.0;
+        }
+        (self.hits + self.stale_hits) as f64 / total as f64
+    }
+}
+
+struct CachedUserInfo {
+    info: AdUserInfo,
+    fetched_at: Instant,
+}
+
+/// TTL cache of AD user info keyed by DN.
+/// When AD request fails, expired entries are still returned for up to `max_stale`.
+#[derive(Clone)]
+pub struct UserInfoCache {
+    ttl: Duration,
+    max_stale: Duration,
+    entries: Arc<Mutex<HashMap<String, CachedUserInfo>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    stale_hits: Arc<AtomicU64>,
+}
+
+impl UserInfoCache {
+    pub fn new(ttl: Duration, max_stale: Duration) -> Self {
+        Self {
+            ttl,
+            max_stale,
+            entries: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            stale_hits: Default::default(),
+        }
+    }
+
+    pub async fn get_or_fetch<F>(&self, dn: &str, fetch: F) -> Result<Option<AdUserInfo>>
+    where
+        F: Future<Output = Result<Option<AdUserInfo>>>,
+    {
+        if let Some(info) = self.get(dn, self.ttl) {
+            record(&self.hits, "hit");
+            return Ok(Some(info));
+        }
+
+        match fetch.await {
+            Ok(info) => {
+                record(&self.misses, "miss");
+                match &info {
+                    Some(info) => self.insert(info.clone()),
+                    None => self.remove(dn),
+                }
+                Ok(info)
+            }
+            Err(err) => match self.get(dn, self.ttl + self.max_stale) {
+                Some(info) => {
+                    warn!("Using cached info of '{dn}', AD request failed: {err}");
+                    record(&self.stale_hits, "stale_hit");
+                    Ok(Some(info))
+                }
+                None => {
+                    record(&self.misses, "miss");
+                    Err(err)
+                }
+            },
+        }
+    }
+
+    pub fn insert(&self, info: AdUserInfo) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            info.dn.clone(),
+            CachedUserInfo {
+                info,
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Logs lookup counters every `period` until `shutdown` is cancelled
+    pub async fn log_stats(self, period: Duration, shutdown: CancellationToken) {
+        let mut interval = tokio::time::interval(period);
+        interval.tick().await;
+        loop {
+            select! {
+                _ = interval.tick() => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let stats = self.stats();
+            info!(
+                hits = stats.hits,
+                misses = stats.misses,
+                stale_hits = stats.stale_hits,
+                "User info cache hit rate: {:.1}%",
+                stats.hit_rate() * 100.0
+            );
+        }
+    }
+
+    fn get(&self, dn: &str, max_age: Duration) -> Option<AdUserInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(dn)
+            .filter(|cached| cached.fetched_at.elapsed() < max_age)
+            .map(|cached| cached.info.clone())
+    }
+
+    fn remove(&self, dn: &str) {
+        self.entries.lock().unwrap().remove(dn);
+    }
+}
+
+fn record(counter: &AtomicU64, result: &str) {
+    counter.fetch_add(1, Ordering::Relaxed);
+    METRICS.ldap_cache.with_label_values(&[result]).inc();
+}
@@ -1,80 +1,87 @@
-use ldap3::Ldap;
-use ldap3::{ResultEntry, SearchEntry};
+pub mod cache;
+pub mod pool;
+pub mod schema;
+
+use std::{collections::HashSet, future::Future, sync::Arc, time::Instant};
+
+use itertools::Itertools;
+use ldap3::{ResultEntry, Scope, SearchEntry};
 use secrecy::{ExposeSecret, SecretString};
 
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use cache::UserInfoCache;
+use pool::{LdapCheck, LdapPool};
+use schema::{GroupEntry, LdapSchema, NestedGroups};
+use tracing::{info, warn};
+
+use crate::auth::AuthProvider;
+use crate::health::HealthCheck;
+use crate::metrics::METRICS;
+
+/// Recursive lookup stops after this many levels of nested groups
+pub const MAX_GROUP_NESTING: usize = 10;
 
 #[derive(Clone)]
 pub struct UsersInfo {
-    pub ldap: Ldap,
-    pub authorized_ldap: Ldap,
+    pub pool: LdapPool,
     pub users_query: String,
+    pub schema: LdapSchema,
+    pub cache: UserInfoCache,
+    /// Where groups are searched with [`NestedGroups::InChain`]
+    in_chain_base: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AdUserInfo {
     pub dn: String,
     pub email: String,
     pub groups: Vec<String>,
 }
 
-impl TryFrom<SearchEntry> for AdUserInfo {
-    type Error = anyhow::Error;
-
-    fn try_from(value: SearchEntry) -> std::result::Result<Self, Self::Error> {
-        let mail = value
-            .attrs
-            .get("mail")
-            .map(|mails| mails.first().cloned())
-            .unwrap_or(None);
-
-        let groups = value.attrs.get("memberOf").map(|groups| {
-            groups
-                .iter()
-                .filter_map(|group| {
-                    group.split(",").find_map(|item| {
-                        item.starts_with("CN=")
-                            .then(|| item.split_once("=").unwrap().1.to_string())
-                    })
-                })
-                .collect()
-        });
-
-        if let (Some(mail), Some(groups)) = (mail, groups) {
-            return Ok(AdUserInfo {
-                email: mail,
-                dn: value.dn,
-                groups,
-            });
-        };
-
-        Err(anyhow!("Unable to parse SearchEntry"))
-    }
-}
-
 impl UsersInfo {
-    pub async fn new(ldap: Ldap, authorized_ldap: Ldap, users_query: String) -> Result<Self> {
+    pub async fn new(
+        pool: LdapPool,
+        users_query: String,
+        schema: LdapSchema,
+        cache: UserInfoCache,
+    ) -> Result<Self> {
+        let in_chain_base = match schema.nested_groups {
+            NestedGroups::InChain => Some(schema.in_chain_base(&users_query)?),
+            _ => None,
+        };
         Ok(Self {
-            ldap,
+            pool,
             users_query,
-            authorized_ldap,
+            schema,
+            cache,
+            in_chain_base,
         })
     }
 
     async fn do_authorized_ldap_request(
         &self,
         query: &str,
+        scope: Scope,
         filter: &str,
+        attrs: Vec<&str>,
     ) -> Result<Vec<ResultEntry>> {
-        let mut ldap = self.authorized_ldap.clone();
-        let res = ldap
-            .search(
-                query,
-                ldap3::Scope::Subtree,
-                filter,
-                vec!["memberOf", "mail", "sAMAccountName"],
-            )
-            .await?;
+        let started = Instant::now();
+        let mut ldap = self.pool.get().await?;
+        let res = match ldap.search(query, scope, filter, attrs.clone()).await {
+            Ok(res) => res,
+            Err(err) => {
+                // pooled connection may be dropped by the server, retry once with a new one
+                warn!("LDAP request failed, retrying with a new connection: {err}");
+                ldap.discard();
+                self.pool
+                    .get()
+                    .await?
+                    .search(query, scope, filter, attrs)
+                    .await?
+            }
+        };
+        METRICS.observe_ldap_request("search", started.elapsed());
 
         match res.success() {
             Ok((rs, _res)) => Ok(rs),
@@ -82,44 +89,140 @@ impl UsersInfo {
         }
     }
 
+    /// Cached, see [`UserInfoCache`]
     pub async fn get_user_info(&self, user_dn: &str) -> Result<Option<AdUserInfo>> {
-        let rs = self.do_authorized_ldap_request(user_dn, "(mail=*)").await?;
-        if let Some(entry) = rs.first() {
-            return Ok(Some(AdUserInfo::try_from(SearchEntry::construct(
-                entry.clone(),
-            ))?));
+        self.cache
+            .get_or_fetch(user_dn, self.fetch_user_info(user_dn))
+            .await
+    }
+
+    async fn fetch_user_info(&self, user_dn: &str) -> Result<Option<AdUserInfo>> {
+        let rs = self
+            .do_authorized_ldap_request(
+                user_dn,
+                Scope::Subtree,
+                &self.schema.user_filter(),
+                self.schema.user_attributes(),
+            )
+            .await?;
+        match rs.into_iter().next() {
+            Some(entry) => Ok(Some(self.parse_user_entry(entry).await?)),
+            None => Ok(None),
         }
-        Ok(None)
     }
 
     pub async fn find_user_info(&self, login_or_mail: String) -> Result<Option<AdUserInfo>> {
         let rs = self
             .do_authorized_ldap_request(
                 &self.users_query,
-                &format!(
-                    "(|(mail={})(sAMAccountName={}))",
-                    login_or_mail, login_or_mail
-                ),
+                Scope::Subtree,
+                &self.schema.login_filter(&login_or_mail),
+                self.schema.user_attributes(),
             )
             .await?;
 
-        if let Some(entry) = rs.first() {
-            return Ok(Some(AdUserInfo::try_from(SearchEntry::construct(
-                entry.clone(),
-            ))?));
+        if let Some(entry) = rs.into_iter().next() {
+            let info = self.parse_user_entry(entry).await?;
+            // refresh cache on login, so changed groups are picked up right away
+            self.cache.insert(info.clone());
+            return Ok(Some(info));
         }
         Ok(None)
     }
 
+    async fn parse_user_entry(&self, entry: ResultEntry) -> Result<AdUserInfo> {
+        let entry = SearchEntry::construct(entry);
+        let groups = match self.schema.nested_groups {
+            NestedGroups::None if self.schema.group_search.is_none() => None,
+            NestedGroups::None => Some(self.group_names(self.member_of(&entry.dn).await?)),
+            NestedGroups::InChain => {
+                Some(self.group_names(self.member_of_in_chain(&entry.dn).await?))
+            }
+            NestedGroups::Recursive => {
+                let direct = match self.schema.group_search {
+                    Some(_) => self.member_of(&entry.dn).await?,
+                    None => self.schema.direct_groups(&entry),
+                };
+                let groups =
+                    with_parent_groups(direct, |dn| async move { self.member_of(&dn).await })
+                        .await?;
+                Some(self.group_names(groups))
+            }
+        };
+        self.schema.user_info(entry, groups)
+    }
+
+    /// Groups `dn` is a direct member of
+    async fn member_of(&self, dn: &str) -> Result<Vec<GroupEntry>> {
+        match &self.schema.group_search {
+            Some(search) => {
+                let rs = self
+                    .do_authorized_ldap_request(
+                        &search.base,
+                        Scope::Subtree,
+                        &search.filter(dn),
+                        vec![search.name_attribute.as_str()],
+                    )
+                    .await?;
+                Ok(rs
+                    .into_iter()
+                    .map(|group| self.schema.group_entry(SearchEntry::construct(group)))
+                    .collect())
+            }
+            None => {
+                let rs = self
+                    .do_authorized_ldap_request(
+                        dn,
+                        Scope::Base,
+                        "(objectClass=*)",
+                        vec![self.schema.group_attribute.as_str()],
+                    )
+                    .await?;
+                Ok(rs
+                    .into_iter()
+                    .next()
+                    .map(|entry| self.schema.direct_groups(&SearchEntry::construct(entry)))
+                    .unwrap_or_default())
+            }
+        }
+    }
+
+    /// All groups `dn` is a member of, resolved by AD itself
+    async fn member_of_in_chain(&self, dn: &str) -> Result<Vec<GroupEntry>> {
+        let base = self
+            .in_chain_base
+            .as_deref()
+            .ok_or_else(|| anyhow!("Nested groups are not resolved in chain"))?;
+        let name_attribute = self.schema.group_name_attribute();
+        let rs = self
+            .do_authorized_ldap_request(
+                base,
+                Scope::Subtree,
+                &self.schema.in_chain_filter(dn),
+                vec![name_attribute],
+            )
+            .await?;
+        Ok(rs
+            .into_iter()
+            .map(|group| self.schema.group_entry(SearchEntry::construct(group)))
+            .collect())
+    }
+
+    fn group_names(&self, groups: Vec<GroupEntry>) -> Vec<String> {
+        groups.into_iter().filter_map(|g| g.name).unique().collect()
+    }
+
     pub async fn check_authentication(&self, user_dn: &str, password: &SecretString) -> Result<()> {
         if password.expose_secret().is_empty() {
             return Err(anyhow!("Empty password"));
         };
-        let mut ldap = self.ldap.clone();
+        let started = Instant::now();
+        let mut ldap = self.pool.connect().await?;
         let result = ldap
             .simple_bind(user_dn, password.expose_secret())
             .await
             .and_then(|r| r.success());
+        METRICS.observe_ldap_request("bind", started.elapsed());
 
         if let Err(err) = result {
             return Err(err.into());
@@ -127,3 +230,71 @@ impl UsersInfo {
         Ok(())
     }
 }
+
+/// Adds groups which `groups` are members of according to `member_of`, and so on
+/// up to [`MAX_GROUP_NESTING`] levels. Every group is looked up once, so cycles are fine.
+pub async fn with_parent_groups<F, Fut>(
+    groups: Vec<GroupEntry>,
+    mut member_of: F,
+) -> Result<Vec<GroupEntry>>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Vec<GroupEntry>>>,
+{
+    let mut seen: HashSet<String> = groups.iter().map(|g| g.dn.clone()).collect();
+    let mut all = groups.clone();
+    let mut level = groups;
+    for _ in 0..MAX_GROUP_NESTING {
+        let mut parents = Vec::new();
+        for group in &level {
+            for parent in member_of(group.dn.clone()).await? {
+                if seen.insert(parent.dn.clone()) {
+                    parents.push(parent);
+                }
+            }
+        }
+        if parents.is_empty() {
+            break;
+        }
+        all.extend(parents.iter().cloned());
+        level = parents;
+    }
+    Ok(all)
+}
+
+#[async_trait]
+impl AuthProvider for UsersInfo {
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &SecretString,
+    ) -> Result<Option<AdUserInfo>> {
+        let Some(u_info) = self.find_user_info(login.to_string()).await? else {
+            info!("Authentication failed for '{}': unknown user", login);
+            return Ok(None);
+        };
+
+        if let Err(err) = self.check_authentication(&u_info.dn, password).await {
+            info!("Authentication failed for '{}': '{}'", login, err);
+            return Ok(None);
+        }
+        Ok(Some(u_info))
+    }
+
+    async fn get_user_info(&self, dn: &str) -> Result<Option<AdUserInfo>> {
+        UsersInfo::get_user_info(self, dn).await
+    }
+
+    fn health_checks(&self) -> Vec<(&'static str, Arc<dyn HealthCheck>)> {
+        vec![
+            (
+                "ldap_service_account",
+                Arc::new(LdapCheck::ServiceAccount(self.pool.clone())),
+            ),
+            (
+                "ldap_connect",
+                Arc::new(LdapCheck::Connect(self.pool.clone())),
+            ),
+        ]
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ldap3::{Ldap, LdapConnAsync, Scope};
+use secrecy::ExposeSecret;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use crate::configuration::LdapSettings;
+use crate::health::HealthCheck;
+
+const CONNECT_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Connection with the permit it's open under
+type Permitted = (Ldap, OwnedSemaphorePermit);
+
+/// Pool of LDAP connections bound with the service account.
+/// Broken connections are dropped and replaced with new ones, so AD restarts are survived.
+/// Every open connection, idle or in use, takes one of `max_connections` permits.
+#[derive(Clone)]
+pub struct LdapPool {
+    settings: LdapSettings,
+    idle: Arc<Mutex<Vec<Permitted>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl LdapPool {
+    pub fn new(settings: LdapSettings) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(settings.max_connections)),
+            settings,
+            idle: Default::default(),
+        }
+    }
+
+    /// Idle connection from the pool or a new one bound with the service account
+    pub async fn get(&self) -> Result<PooledLdap> {
+        loop {
+            let Some((mut ldap, permit)) = self.idle.lock().unwrap().pop() else {
+                break;
+            };
+            if !ldap.is_closed() {
+                return Ok(PooledLdap {
+                    conn: Some((ldap, permit)),
+                    pool: self.clone(),
+                });
+            }
+        }
+
+        let mut conn = self.connect().await?;
+        conn.simple_bind(&self.settings.login, self.settings.password.expose_secret())
+            .await?
+            .success()
+            .context("Failed to bind LDAP service account")?;
+        Ok(PooledLdap {
+            conn: Some((conn.ldap, conn.permit)),
+            pool: self.clone(),
+        })
+    }
+
+    /// New unbound connection which isn't returned to the pool, e.g. to check user credentials.
+    /// Waits for a permit if `max_connections` are open.
+    pub async fn connect(&self) -> Result<LdapConn> {
+        let permit = self.permit().await;
+        Ok(LdapConn {
+            ldap: self.open().await?,
+            permit,
+        })
+    }
+
+    /// Closes an idle connection if there are no free permits
+    async fn permit(&self) -> OwnedSemaphorePermit {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return permit;
+        }
+        if let Some((_, permit)) = self.idle.lock().unwrap().pop() {
+            return permit;
+        }
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("LDAP pool semaphore is never closed")
+    }
+
+    async fn open(&self) -> Result<Ldap> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match LdapConnAsync::with_settings(self.settings.clone().into(), &self.settings.url)
+                .await
+            {
+                Ok((conn, ldap)) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = conn.drive().await {
+                            warn!("LDAP connection closed: {err}");
+                        }
+                    });
+                    return Ok(ldap);
+                }
+                Err(err) if attempt < CONNECT_ATTEMPTS => {
+                    warn!(
+                        "Failed to connect to LDAP (attempt {attempt}), retrying in {backoff:?}: {err}"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err).context("Failed to connect to LDAP"),
+            }
+        }
+    }
+
+    /// Checks that LDAP is reachable and the service account can bind
+    pub async fn check(&self) {
+        match self.get().await {
+            Ok(_) => info!("Connected to LDAP"),
+            Err(err) => warn!("LDAP is unavailable, will reconnect on demand: {err:#}"),
+        }
+    }
+
+    fn release(&self, (mut ldap, permit): Permitted) {
+        if ldap.is_closed() {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.settings.pool_size {
+            idle.push((ldap, permit));
+        }
+    }
+}
+
+/// Readiness of LDAP for the health endpoint
+pub enum LdapCheck {
+    /// Pooled connection bound with the service account answers requests, used to look up users
+    ServiceAccount(LdapPool),
+    /// New connections can be opened, used to check passwords of users
+    Connect(LdapPool),
+}
+
+#[async_trait]
+impl HealthCheck for LdapCheck {
+    async fn check(&self) -> Result<()> {
+        match self {
+            LdapCheck::ServiceAccount(pool) => {
+                let mut ldap = pool.get().await?;
+                let result = ldap
+                    .search("", Scope::Base, "(objectClass=*)", vec!["1.1"])
+                    .await
+                    .and_then(|res| res.success());
+                if let Err(err) = result {
+                    ldap.discard();
+                    return Err(err).context("Root DSE request failed");
+                }
+            }
+            LdapCheck::Connect(pool) => {
+                pool.connect().await?.unbind().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Connection which is returned to the pool on drop
+pub struct PooledLdap {
+    conn: Option<Permitted>,
+    pool: LdapPool,
+}
+
+impl PooledLdap {
+    /// Drops the connection instead of returning it to the pool
+    pub fn discard(mut self) {
+        self.conn.take();
+    }
+}
+
+impl Deref for PooledLdap {
+    type Target = Ldap;
+    fn deref(&self) -> &Self::Target {
+        &self.conn.as_ref().unwrap().0
+    }
+}
+
+impl DerefMut for PooledLdap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn.as_mut().unwrap().0
+    }
+}
+
+impl Drop for PooledLdap {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.release(conn);
+        }
+    }
+}
+
+/// Connection outside of the pool, its permit is freed on drop
+pub struct LdapConn {
+    ldap: Ldap,
+    permit: OwnedSemaphorePermit,
+}
+
+impl Deref for LdapConn {
+    type Target = Ldap;
+    fn deref(&self) -> &Self::Target {
+        &self.ldap
+    }
+}
+
+impl DerefMut for LdapConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.ldap
+    }
+}
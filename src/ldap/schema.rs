@@ -0,0 +1,211 @@
+use anyhow::{Context, Result, bail};
+use itertools::Itertools;
+use ldap3::{SearchEntry, ldap_escape};
+use serde::Deserialize;
+
+use super::AdUserInfo;
+
+/// AD matching rule to check membership through nested groups
+const MATCHING_RULE_IN_CHAIN: &str = "1.2.840.113556.1.4.1941";
+
+/// How users and their groups are looked up, defaults match AD
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LdapSchema {
+    /// Filter to find user on login, `{login}` is replaced with the entered login
+    pub login_filter: String,
+    pub email_attribute: String,
+    /// Attribute of user entry with groups, e.g. `memberOf`. Not used with `group_search`
+    pub group_attribute: String,
+    pub group_name: GroupName,
+    /// Look up groups with a separate search, for servers without `memberOf`
+    pub group_search: Option<GroupSearch>,
+    /// Whether groups of groups are included, so limits granted to parent groups apply
+    pub nested_groups: NestedGroups,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NestedGroups {
+    /// Only groups user is a direct member of
+    #[default]
+    None,
+    /// Resolved by AD with `LDAP_MATCHING_RULE_IN_CHAIN` in a single request
+    InChain,
+    /// Resolved with a request per group, works with any server
+    Recursive,
+}
+
+/// Group DN with its name, if it could be extracted
+#[derive(Clone, Debug)]
+pub struct GroupEntry {
+    pub dn: String,
+    pub name: Option<String>,
+}
+
+impl Default for LdapSchema {
+    fn default() -> Self {
+        Self {
+            login_filter: "(|(mail={login})(sAMAccountName={login}))".into(),
+            email_attribute: "mail".into(),
+            group_attribute: "memberOf".into(),
+            group_name: GroupName::Cn,
+            group_search: None,
+            nested_groups: NestedGroups::None,
+        }
+    }
+}
+
+/// How group name is taken from the group attribute value
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupName {
+    /// `CN` of the group DN, e.g. `admins` for `cn=admins,cn=groups,dc=example,dc=org`
+    #[default]
+    Cn,
+    /// Value as is
+    Value,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct GroupSearch {
+    pub base: String,
+    /// `{dn}` is replaced with the user DN
+    #[serde(default = "default_group_filter")]
+    pub filter: String,
+    /// Attribute of group entry with its name
+    #[serde(default = "default_group_name_attribute")]
+    pub name_attribute: String,
+}
+
+fn default_group_filter() -> String {
+    "(&(objectClass=groupOfNames)(member={dn}))".into()
+}
+
+fn default_group_name_attribute() -> String {
+    "cn".into()
+}
+
+impl LdapSchema {
+    /// Login is escaped according to RFC 4515
+    pub fn login_filter(&self, login: &str) -> String {
+        self.login_filter.replace("{login}", &ldap_escape(login))
+    }
+
+    /// Filter which matches any user entry, used to fetch user by DN
+    pub fn user_filter(&self) -> String {
+        format!("({}=*)", self.email_attribute)
+    }
+
+    /// Attributes requested for user entries
+    pub fn user_attributes(&self) -> Vec<&str> {
+        match self.group_search {
+            Some(_) => vec![self.email_attribute.as_str()],
+            None => vec![self.email_attribute.as_str(), self.group_attribute.as_str()],
+        }
+    }
+
+    /// Groups from the group attribute of user or group entry
+    pub fn direct_groups(&self, entry: &SearchEntry) -> Vec<GroupEntry> {
+        entry
+            .attrs
+            .get(&self.group_attribute)
+            .map(|groups| {
+                groups
+                    .iter()
+                    .map(|dn| GroupEntry {
+                        dn: dn.clone(),
+                        name: self.group_name(dn),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Attribute requested for group entries found with a search
+    pub fn group_name_attribute(&self) -> &str {
+        match &self.group_search {
+            Some(search) => &search.name_attribute,
+            None => "cn",
+        }
+    }
+
+    /// Group found with a search, see [`Self::group_name_attribute`]
+    pub fn group_entry(&self, entry: SearchEntry) -> GroupEntry {
+        let name = match &self.group_search {
+            Some(search) => entry
+                .attrs
+                .get(&search.name_attribute)
+                .and_then(|names| names.first().cloned()),
+            None => self.group_name(&entry.dn),
+        };
+        GroupEntry { dn: entry.dn, name }
+    }
+
+    pub fn group_name(&self, value: &str) -> Option<String> {
+        match self.group_name {
+            GroupName::Value => Some(value.to_string()),
+            GroupName::Cn => value.split(',').find_map(|rdn| {
+                let (attr, name) = rdn.split_once('=')?;
+                attr.trim()
+                    .eq_ignore_ascii_case("cn")
+                    .then(|| name.trim().to_string())
+            }),
+        }
+    }
+
+    /// Base of the search for groups with [`NestedGroups::InChain`]: `group_search.base` if set,
+    /// otherwise the domain of `users_query`, so groups outside of the users OU are found too
+    pub fn in_chain_base(&self, users_query: &str) -> Result<String> {
+        if let Some(search) = &self.group_search {
+            return Ok(search.base.clone());
+        }
+        let domain = users_query
+            .split(',')
+            .map(str::trim)
+            .skip_while(|rdn| {
+                !rdn.split_once('=')
+                    .is_some_and(|(attr, _)| attr.trim().eq_ignore_ascii_case("dc"))
+            })
+            .join(",");
+        if domain.is_empty() {
+            bail!(
+                "Domain of '{users_query}' is unknown, set [ldap.schema.group_search] base to resolve nested groups"
+            );
+        }
+        Ok(domain)
+    }
+
+    /// Filter which matches all groups `dn` is a member of, directly or through other groups.
+    /// DN is escaped according to RFC 4515
+    pub fn in_chain_filter(&self, dn: &str) -> String {
+        format!("(member:{MATCHING_RULE_IN_CHAIN}:={})", ldap_escape(dn))
+    }
+
+    /// Groups are taken from the group attribute unless `groups` are looked up separately
+    pub fn user_info(&self, entry: SearchEntry, groups: Option<Vec<String>>) -> Result<AdUserInfo> {
+        let email = entry
+            .attrs
+            .get(&self.email_attribute)
+            .and_then(|emails| emails.first().cloned())
+            .with_context(|| format!("'{}' has no '{}'", entry.dn, self.email_attribute))?;
+        let groups = groups.unwrap_or_else(|| {
+            self.direct_groups(&entry)
+                .into_iter()
+                .filter_map(|g| g.name)
+                .collect()
+        });
+        Ok(AdUserInfo {
+            dn: entry.dn,
+            email,
+            groups,
+        })
+    }
+}
+
+impl GroupSearch {
+    /// DN is escaped according to RFC 4515
+    pub fn filter(&self, dn: &str) -> String {
+        self.filter.replace("{dn}", &ldap_escape(dn))
+    }
+}
@@ -1,10 +1,14 @@
 use std::path::Path;
 
+pub mod auth;
 pub mod bot;
 pub mod configuration;
 pub mod db;
+pub mod health;
 pub mod ldap;
 pub mod logic;
+pub mod metrics;
+pub mod shutdown;
 pub mod telemetry;
 pub mod web;
 
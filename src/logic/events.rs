@@ -0,0 +1,104 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::postgres::PgListener;
+use tokio::{select, sync::broadcast};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::db::{LEASES_CHANNEL, Registry, RegistryTx, models::HostId};
+
+/// Events which weren't received yet by a slow subscriber before it starts to miss them
+const CAPACITY: usize = 64;
+
+/// Hosts sent in one notification, its payload is limited to 8000 bytes
+const NOTIFY_HOSTS: usize = 500;
+
+/// Change of hosts leases, sent to open hosts pages
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", content = "hosts", rename_all = "lowercase")]
+pub enum HostsEvent {
+    Leased(#[serde(deserialize_with = "host_ids")] Vec<HostId>),
+    Released(#[serde(deserialize_with = "host_ids")] Vec<HostId>),
+    /// Note of leased hosts was changed
+    Updated(#[serde(deserialize_with = "host_ids")] Vec<HostId>),
+}
+
+/// [`HostId`] is parsed from strings of forms, notifications have numbers
+fn host_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<HostId>, D::Error> {
+    Ok(Vec::<i32>::deserialize(deserializer)?
+        .into_iter()
+        .map(HostId)
+        .collect())
+}
+
+impl HostsEvent {
+    /// Sends the event to [`HostsEvents`] of every instance once `tx` is committed
+    pub async fn notify(&self, tx: &mut RegistryTx<'_>) -> sqlx::Result<()> {
+        let (kind, hosts): (fn(Vec<HostId>) -> Self, _) = match self {
+            Self::Leased(hosts) => (Self::Leased, hosts),
+            Self::Released(hosts) => (Self::Released, hosts),
+            Self::Updated(hosts) => (Self::Updated, hosts),
+        };
+        for chunk in hosts.chunks(NOTIFY_HOSTS) {
+            let payload =
+                serde_json::to_string(&kind(chunk.to_vec())).expect("Hosts event is serializable");
+            tx.notify(LEASES_CHANNEL, &payload).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Broadcast of [`HostsEvent`] of all instances, only subscribers at the moment of receiving get an event
+#[derive(Clone)]
+pub struct HostsEvents {
+    sender: broadcast::Sender<HostsEvent>,
+}
+
+/// Doesn't receive any events, for components which don't show them
+impl Default for HostsEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl HostsEvents {
+    /// Receives events notified through the database until `shutdown` is cancelled
+    pub async fn listen(registry: &Registry, shutdown: CancellationToken) -> sqlx::Result<Self> {
+        let events = Self::default();
+        let listener = registry.listen(LEASES_CHANNEL).await?;
+        tokio::spawn(events.clone().relay(listener, shutdown));
+        Ok(events)
+    }
+
+    async fn relay(self, mut listener: PgListener, shutdown: CancellationToken) {
+        loop {
+            let notification = select! {
+                notification = listener.try_recv() => notification,
+                _ = shutdown.cancelled() => return,
+            };
+            match notification {
+                // the trigger notifies with an empty payload
+                Ok(Some(notification)) if notification.payload().is_empty() => {}
+                Ok(Some(notification)) => match serde_json::from_str(notification.payload()) {
+                    // no one is subscribed
+                    Ok(event) => _ = self.sender.send(event),
+                    Err(err) => warn!("Invalid hosts event {}: {err}", notification.payload()),
+                },
+                // reconnects on the next receive
+                Ok(None) => warn!("Lost connection for hosts events, some could be missed"),
+                Err(err) => {
+                    error!("Failed to receive hosts events: {err}");
+                    select! {
+                        _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {}
+                        _ = shutdown.cancelled() => return,
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<HostsEvent> {
+        self.sender.subscribe()
+    }
+}
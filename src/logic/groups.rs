@@ -1,6 +1,12 @@
+use std::collections::{HashMap, HashSet};
+
 use thiserror::Error;
 
-use crate::db::{Registry, models::Group};
+use super::policies::LeasePolicies;
+use crate::db::{
+    Registry,
+    models::{Group, GroupId},
+};
 
 #[derive(Error, Debug)]
 pub enum GroupError {
@@ -8,6 +14,16 @@ pub enum GroupError {
     DatabaseError(#[from] sqlx::Error),
 }
 
+/// Group with its position in the hierarchy
+#[derive(Clone, Debug)]
+pub struct GroupNode {
+    pub group: Group,
+    /// Names of all ancestors and the group itself joined with `/`, e.g. `perf/arm`.
+    /// Root isn't included in paths of its descendants.
+    pub path: String,
+    pub depth: usize,
+}
+
 #[derive(Clone)]
 pub struct GroupsService {
     registry: Registry,
@@ -24,4 +40,61 @@ impl GroupsService {
         tx.commit().await?;
         Ok(groups)
     }
+
+    /// Groups tree without groups user has no access to.
+    /// Inaccessible groups are kept if some of their descendants are accessible.
+    pub async fn get_accessible_groups_tree(
+        &self,
+        user_groups: &[String],
+    ) -> Result<Vec<GroupNode>, GroupError> {
+        let mut tx = self.registry.begin().await?;
+        let tree = groups_tree(tx.get_groups().await?);
+        let policies = LeasePolicies::load(&mut tx).await?;
+        tx.commit().await?;
+
+        let visible: HashSet<GroupId> = tree
+            .iter()
+            .filter(|node| policies.is_accessible(&node.group.id, user_groups))
+            .flat_map(|node| policies.ancestors(&node.group.id))
+            .collect();
+        Ok(tree
+            .into_iter()
+            .filter(|node| visible.contains(&node.group.id))
+            .collect())
+    }
+}
+
+/// Orders groups depth-first, so every group is followed by its descendants.
+/// Siblings keep the order of `groups`.
+pub fn groups_tree(groups: Vec<Group>) -> Vec<GroupNode> {
+    let mut children: HashMap<Option<GroupId>, Vec<Group>> = HashMap::new();
+    for group in groups {
+        children.entry(group.parent_id).or_default().push(group);
+    }
+
+    let mut tree = Vec::new();
+    let mut stack: Vec<(Group, String, usize)> = children
+        .remove(&None)
+        .unwrap_or_default()
+        .into_iter()
+        .rev()
+        .map(|g| (g.clone(), g.name, 0))
+        .collect();
+    while let Some((group, path, depth)) = stack.pop() {
+        if let Some(nested) = children.remove(&Some(group.id)) {
+            let prefix = if group.id == GroupId::ROOT {
+                String::new()
+            } else {
+                format!("{path}/")
+            };
+            stack.extend(
+                nested
+                    .into_iter()
+                    .rev()
+                    .map(|g| (g.clone(), format!("{prefix}{}", g.name), depth + 1)),
+            );
+        }
+        tree.push(GroupNode { group, path, depth });
+    }
+    tree
 }
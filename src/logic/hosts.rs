@@ -1,13 +1,19 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use chrono::Utc;
+use chrono::{TimeDelta, Utc};
+use itertools::Itertools;
+use reqwest::Url;
 use thiserror::Error;
+use tracing::instrument;
 
+use super::events::{HostsEvent, HostsEvents};
+use super::policies::{LeasePolicies, LeasePolicy};
 use crate::db::RegistryTx;
 use crate::db::{
     Registry,
-    models::{GroupId, Host, HostId, LeasedHost, UserId},
+    models::{GroupId, Host, HostId, LeaseNote, LeasedHost, UserId},
 };
+use crate::metrics::METRICS;
 
 #[derive(Error, Debug)]
 pub enum HostError {
@@ -23,14 +29,40 @@ pub enum HostError {
     #[error("Hosts lease limit is reached")]
     LeaseLimit,
 
+    #[error("Host is retired")]
+    Retired(Vec<HostId>),
+
+    #[error("Hosts of group '{0}' are not available to you")]
+    GroupForbidden(String),
+
+    #[error("Hosts of group '{group}' can't be leased for more than {max_hours} hours")]
+    LeaseTooLong { group: String, max_hours: i64 },
+
+    #[error("Hosts lease limit of group '{0}' is reached")]
+    GroupLeaseLimit(String),
+
+    #[error("{0}")]
+    InvalidNote(String),
+
     #[error("Unexpected error")]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+/// Used when neither lease duration nor group default is set
+pub const DEFAULT_LEASE_DURATION: TimeDelta = TimeDelta::hours(1);
+/// Used when no group policy sets the maximum lease
+pub const MAX_LEASE_DURATION: TimeDelta = TimeDelta::days(63);
+
+/// Longest lease note, in characters
+pub const MAX_NOTE_LENGTH: usize = 500;
+/// Longest ticket link, in characters
+pub const MAX_TICKET_LENGTH: usize = 500;
+
 #[derive(Clone)]
 pub struct HostsService {
     registry: Registry,
     lease_limit: usize,
+    events: HostsEvents,
 }
 
 impl HostsService {
@@ -38,9 +70,21 @@ impl HostsService {
         HostsService {
             registry,
             lease_limit,
+            events: HostsEvents::default(),
         }
     }
 
+    /// Events of all instances shown on hosts pages, see [`HostsEvents::listen`]
+    pub fn with_events(mut self, events: HostsEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn events(&self) -> &HostsEvents {
+        &self.events
+    }
+
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_all_hosts(&self) -> Result<Vec<Host>, HostError> {
         let mut tx = self.registry.begin().await?;
         let hosts = tx.get_all_hosts().await?;
@@ -48,17 +92,27 @@ impl HostsService {
         Ok(hosts)
     }
 
+    /// Hosts of the group and its descendants, except for groups user has no access to
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_available_group_hosts(
         &self,
         group_id: &GroupId,
+        user_groups: &[String],
     ) -> Result<Vec<Host>, HostError> {
         let mut tx = self.registry.begin().await?;
-        let hosts = tx.get_available_group_hosts(group_id).await?;
+        let policies = LeasePolicies::load(&mut tx).await?;
+        let hosts = tx
+            .get_available_group_hosts(group_id)
+            .await?
+            .into_iter()
+            .filter(|h| policies.is_accessible(&h.group_id, user_groups))
+            .collect();
 
         tx.commit().await?;
         Ok(hosts)
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_available_hosts(&self) -> Result<Vec<Host>, HostError> {
         let mut tx = self.registry.begin().await?;
         let hosts = tx.get_available_hosts().await?;
@@ -66,6 +120,7 @@ impl HostsService {
         Ok(hosts)
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn get_leased_hosts(&self, user_id: &UserId) -> Result<Vec<LeasedHost>, HostError> {
         let mut tx = self.registry.begin().await?;
         let hosts = tx.get_leased_hosts(user_id).await?;
@@ -73,13 +128,16 @@ impl HostsService {
         Ok(hosts)
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn lease(
         &self,
         user_id: &UserId,
         user_groups: &Vec<String>,
         hosts_ids: &[HostId],
-        lease_for: chrono::TimeDelta,
+        lease_for: Option<TimeDelta>,
+        note: &LeaseNote,
     ) -> Result<Vec<LeasedHost>, HostError> {
+        check_note(note)?;
         let mut tx = self.registry.begin().await?;
         let leased: HashSet<_> = tx
             .get_leased_hosts(user_id)
@@ -98,6 +156,21 @@ impl HostsService {
             ));
         }
 
+        let hosts = tx.get_hosts(hosts_ids).await?;
+        let retired: Vec<_> = hosts
+            .iter()
+            .filter(|h| h.retired_at.is_some())
+            .map(|h| h.id)
+            .collect();
+        if !retired.is_empty() {
+            return Err(HostError::Retired(retired));
+        }
+
+        let policies = LeasePolicies::load(&mut tx).await?;
+        let lease_for = self
+            .check_policies(&mut tx, &policies, user_id, user_groups, &hosts, lease_for)
+            .await?;
+
         let lease_limit = self.get_lease_limit(&mut tx, user_groups).await?;
         if leased.len() + hosts_ids_set.len() > lease_limit {
             return Err(HostError::LeaseLimit);
@@ -105,52 +178,216 @@ impl HostsService {
 
         tx.lease_hosts(user_id, hosts_ids, Utc::now() + lease_for)
             .await?;
+        if !note.is_empty() {
+            tx.set_lease_note(user_id, hosts_ids, note).await?;
+        }
+
+        HostsEvent::Leased(hosts_ids.to_vec())
+            .notify(&mut tx)
+            .await?;
 
         let leased = tx.get_leased_hosts(user_id).await?;
         tx.commit().await?;
+        for _ in hosts_ids {
+            observe_lease(lease_for);
+        }
         Ok(leased)
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn free(&self, user_id: &UserId, hosts_ids: &[HostId]) -> Result<(), HostError> {
         let mut tx = self.registry.begin().await?;
-        tx.free_hosts_for_user(hosts_ids.as_ref(), user_id).await?;
+        let released = tx.free_hosts_for_user(hosts_ids.as_ref(), user_id).await?;
+        HostsEvent::Released(released).notify(&mut tx).await?;
         tx.commit().await?;
         Ok(())
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn free_all(&self, user_id: &UserId) -> Result<(), HostError> {
         let mut tx = self.registry.begin().await?;
 
-        tx.free_all(user_id).await?;
+        let released = tx.free_all(user_id).await?;
+        HostsEvent::Released(released).notify(&mut tx).await?;
         tx.commit().await?;
 
         Ok(())
     }
 
+    #[instrument(level = "debug", skip(self))]
     pub async fn lease_random(
         &self,
         user_id: &UserId,
         user_groups: &Vec<String>,
-        lease_for: chrono::TimeDelta,
+        lease_for: Option<TimeDelta>,
         group_id: &GroupId,
+        note: &LeaseNote,
     ) -> Result<LeasedHost, HostError> {
+        check_note(note)?;
         let mut tx = self.registry.begin().await?;
         let lease_limit = self.get_lease_limit(&mut tx, user_groups).await?;
 
         if tx.get_leased_hosts(user_id).await?.len() >= lease_limit {
             return Err(HostError::LeaseLimit);
         };
-        if let Some(host) = tx.get_first_available_group_host(group_id).await? {
-            tx.lease_hosts(user_id, &[host.id], Utc::now() + lease_for)
-                .await?;
-            let leased = tx.get_leased_host(&host.id).await?;
-            tx.commit().await?;
 
-            return Ok(leased);
+        // Hosts may belong to descendant groups with their own policies,
+        // pick the first one allowed by them
+        let policies = LeasePolicies::load(&mut tx).await?;
+        let candidates: Vec<_> = tx
+            .get_available_group_hosts(group_id)
+            .await?
+            .into_iter()
+            .unique_by(|h| h.group_id)
+            .collect();
+        let mut policy_error = None;
+        for host in candidates {
+            let checked = self
+                .check_policies(
+                    &mut tx,
+                    &policies,
+                    user_id,
+                    user_groups,
+                    std::slice::from_ref(&host),
+                    lease_for,
+                )
+                .await;
+            match checked {
+                Ok(lease_for) => {
+                    tx.lease_hosts(user_id, &[host.id], Utc::now() + lease_for)
+                        .await?;
+                    if !note.is_empty() {
+                        tx.set_lease_note(user_id, &[host.id], note).await?;
+                    }
+                    HostsEvent::Leased(vec![host.id]).notify(&mut tx).await?;
+                    let leased = tx.get_leased_host(&host.id).await?;
+                    tx.commit().await?;
+                    observe_lease(lease_for);
+
+                    return Ok(leased);
+                }
+                Err(HostError::DatabaseError(err)) => return Err(err.into()),
+                Err(err) => {
+                    policy_error.get_or_insert(err);
+                }
+            }
+        }
+        Err(policy_error.unwrap_or(HostError::ThereIsNoFreeHosts))
+    }
+
+    /// Changes the note of hosts leased by the user, other hosts are skipped
+    #[instrument(level = "debug", skip(self))]
+    pub async fn set_note(
+        &self,
+        user_id: &UserId,
+        hosts_ids: &[HostId],
+        note: &LeaseNote,
+    ) -> Result<(), HostError> {
+        check_note(note)?;
+        let mut tx = self.registry.begin().await?;
+        let updated = tx.set_lease_note(user_id, hosts_ids, note).await?;
+        HostsEvent::Updated(updated).notify(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub async fn get_group_policy(&self, group_id: &GroupId) -> Result<LeasePolicy, HostError> {
+        let mut tx = self.registry.begin().await?;
+        let policy = LeasePolicies::load(&mut tx).await?.effective(group_id);
+        tx.commit().await?;
+        Ok(policy)
+    }
+
+    /// Refreshes gauges of hosts and leasing users by group in [`METRICS`]
+    #[instrument(level = "debug", skip(self))]
+    pub async fn update_metrics(&self) -> Result<(), HostError> {
+        let mut tx = self.registry.begin().await?;
+        let counts = tx.count_hosts_by_group().await?;
+        let policies = LeasePolicies::load(&mut tx).await?;
+        tx.commit().await?;
+
+        METRICS.hosts.reset();
+        METRICS.leasing_users.reset();
+        for count in counts {
+            let group = policies.path(&count.group_id);
+            for (state, hosts) in [
+                ("free", count.free),
+                ("leased", count.leased),
+                ("retired", count.retired),
+            ] {
+                METRICS
+                    .hosts
+                    .with_label_values(&[group.as_str(), state])
+                    .set(hosts);
+            }
+            METRICS
+                .leasing_users
+                .with_label_values(&[group.as_str()])
+                .set(count.users);
+        }
+        Ok(())
+    }
+
+    /// Checks policies of the groups `hosts` belong to and resolves lease duration
+    async fn check_policies(
+        &self,
+        tx: &mut RegistryTx<'_>,
+        policies: &LeasePolicies,
+        user_id: &UserId,
+        user_groups: &[String],
+        hosts: &[Host],
+        lease_for: Option<TimeDelta>,
+    ) -> Result<TimeDelta, HostError> {
+        let mut hosts_by_group: HashMap<GroupId, usize> = HashMap::new();
+        for host in hosts {
+            *hosts_by_group.entry(host.group_id).or_default() += 1;
+        }
+        let groups: Vec<_> = hosts_by_group
+            .keys()
+            .map(|group_id| (*group_id, policies.effective(group_id)))
+            .collect();
+
+        if let Some((group_id, _)) = groups.iter().find(|(_, p)| !p.allows(user_groups)) {
+            return Err(HostError::GroupForbidden(policies.path(group_id)));
+        }
+
+        let lease_for = lease_for.unwrap_or_else(|| {
+            groups
+                .iter()
+                .filter_map(|(_, p)| p.default_lease)
+                .min()
+                .unwrap_or(DEFAULT_LEASE_DURATION)
+        });
+        for (group_id, policy) in &groups {
+            let max = policy.max_lease.unwrap_or(MAX_LEASE_DURATION);
+            if lease_for > max {
+                return Err(HostError::LeaseTooLong {
+                    group: policies.path(group_id),
+                    max_hours: max.num_hours(),
+                });
+            }
+        }
+
+        let mut new_by_limit: HashMap<GroupId, (usize, usize)> = HashMap::new();
+        for (group_id, policy) in &groups {
+            if let Some((limit_group, limit)) = policy.user_limit {
+                new_by_limit.entry(limit_group).or_insert((limit, 0)).1 += hosts_by_group[group_id];
+            }
+        }
+        for (limit_group, (limit, new)) in new_by_limit {
+            let leased = tx
+                .count_user_leases_in_groups(user_id, &policies.subtree(&limit_group))
+                .await?;
+            if leased as usize + new > limit {
+                return Err(HostError::GroupLeaseLimit(policies.path(&limit_group)));
+            }
         }
-        Err(HostError::ThereIsNoFreeHosts)
+
+        Ok(lease_for)
     }
 
+    #[instrument(level = "debug", skip(self, tx))]
     pub async fn get_lease_limit(
         &self,
         tx: &mut RegistryTx<'_>,
@@ -166,3 +403,42 @@ impl HostsService {
         Ok(limit)
     }
 }
+
+fn observe_lease(lease_for: TimeDelta) {
+    METRICS
+        .lease_duration
+        .observe(lease_for.num_minutes() as f64 / 60.0);
+}
+
+/// Note is limited in length, ticket must be a web link since hosts pages show it as one
+fn check_note(note: &LeaseNote) -> Result<(), HostError> {
+    if note
+        .note
+        .as_ref()
+        .is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH)
+    {
+        return Err(HostError::InvalidNote(format!(
+            "Note can't be longer than {MAX_NOTE_LENGTH} characters"
+        )));
+    }
+    if note
+        .ticket
+        .as_ref()
+        .is_some_and(|ticket| ticket.chars().count() > MAX_TICKET_LENGTH)
+    {
+        return Err(HostError::InvalidNote(format!(
+            "Ticket link can't be longer than {MAX_TICKET_LENGTH} characters"
+        )));
+    }
+    if let Some(ticket) = &note.ticket {
+        match Url::parse(ticket) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => {
+                return Err(HostError::InvalidNote(format!(
+                    "Ticket '{ticket}' is not an http(s) link"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
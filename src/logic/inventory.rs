@@ -0,0 +1,700 @@
+use std::{collections::HashMap, fmt::Display, net::IpAddr, path::Path, str::FromStr};
+
+use anyhow::Context;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use thiserror::Error;
+use tokio::{
+    select,
+    signal::unix::{SignalKind, signal},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use super::groups::groups_tree;
+use crate::configuration::InventorySettings;
+use crate::db::{
+    Registry, RegistryTx,
+    models::{GroupId, HostId},
+};
+
+#[derive(Error, Debug)]
+pub enum InventoryError {
+    #[error("Database error")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Invalid inventory:\n{}", .0.iter().join("\n"))]
+    Invalid(Vec<LineError>),
+
+    #[error("Failed to render inventory: {0}")]
+    Render(String),
+}
+
+/// Validation error tied to the line of the source file it was found on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InventoryRecord {
+    pub hostname: String,
+    pub ip_address: IpAddr,
+    /// Path of the group, nested groups are separated with `/`, e.g. `perf/arm`
+    pub group: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Display for InventoryRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) group={} tags=[{}]",
+            self.hostname,
+            self.ip_address,
+            self.group,
+            self.tags.join(", ")
+        )
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct CsvRecord {
+    hostname: String,
+    ip_address: String,
+    group: String,
+    #[serde(default)]
+    tags: String,
+}
+
+/// Record as it is written in a file, before validation.
+#[derive(Deserialize)]
+struct RawRecord {
+    hostname: String,
+    ip_address: String,
+    group: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// TOML documents can't be arrays, so hosts are kept under `[[hosts]]` tables
+#[derive(Serialize)]
+struct TomlInventory<'a> {
+    hosts: &'a [InventoryRecord],
+}
+
+#[derive(Deserialize)]
+struct RawTomlInventory {
+    #[serde(default)]
+    hosts: Vec<toml::Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InventoryFormat {
+    Csv,
+    Yaml,
+    Toml,
+}
+
+impl FromStr for InventoryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "toml" => Ok(Self::Toml),
+            other => Err(format!(
+                "Unsupported inventory format: {other}. Use `csv`, `yaml` or `toml`"
+            )),
+        }
+    }
+}
+
+impl InventoryFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Yaml => "application/yaml",
+            Self::Toml => "application/toml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+        }
+    }
+
+    /// Parses and validates inventory, collecting errors from every line instead of
+    /// stopping at the first one.
+    pub fn parse(&self, content: &str) -> Result<Vec<InventoryRecord>, InventoryError> {
+        let raw = match self {
+            Self::Csv => parse_csv(content)?,
+            Self::Yaml => parse_yaml(content)?,
+            Self::Toml => parse_toml(content)?,
+        };
+
+        let mut errors = Vec::new();
+        let mut records = Vec::new();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (line, raw) in raw {
+            match validate(raw) {
+                Ok(record) => {
+                    if let Some(first) = seen.get(&record.hostname) {
+                        errors.push(LineError {
+                            line,
+                            message: format!(
+                                "duplicate hostname '{}', first defined on line {first}",
+                                record.hostname
+                            ),
+                        });
+                        continue;
+                    }
+                    seen.insert(record.hostname.clone(), line);
+                    records.push(record);
+                }
+                Err(message) => errors.push(LineError { line, message }),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(InventoryError::Invalid(errors));
+        }
+        Ok(records)
+    }
+
+    pub fn render(&self, records: &[InventoryRecord]) -> Result<String, InventoryError> {
+        match self {
+            Self::Csv => {
+                let mut writer = csv::Writer::from_writer(vec![]);
+                for record in records {
+                    writer
+                        .serialize(CsvRecord {
+                            hostname: record.hostname.clone(),
+                            ip_address: record.ip_address.to_string(),
+                            group: record.group.clone(),
+                            tags: record.tags.join(";"),
+                        })
+                        .map_err(|e| InventoryError::Render(e.to_string()))?;
+                }
+                let bytes = writer
+                    .into_inner()
+                    .map_err(|e| InventoryError::Render(e.to_string()))?;
+                String::from_utf8(bytes).map_err(|e| InventoryError::Render(e.to_string()))
+            }
+            Self::Yaml => {
+                serde_yaml::to_string(records).map_err(|e| InventoryError::Render(e.to_string()))
+            }
+            Self::Toml => toml::to_string(&TomlInventory { hosts: records })
+                .map_err(|e| InventoryError::Render(e.to_string())),
+        }
+    }
+}
+
+fn parse_csv(content: &str) -> Result<Vec<(usize, RawRecord)>, InventoryError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let headers = reader.headers().cloned().map_err(|err| {
+        InventoryError::Invalid(vec![LineError {
+            line: 1,
+            message: err.to_string(),
+        }])
+    })?;
+
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    for result in reader.records() {
+        let row = match result {
+            Ok(row) => row,
+            Err(err) => {
+                errors.push(LineError {
+                    line: err.position().map_or(0, |p| p.line() as usize),
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = row.position().map_or(0, |p| p.line() as usize);
+        match row.deserialize::<CsvRecord>(Some(&headers)) {
+            Ok(record) => records.push((
+                line,
+                RawRecord {
+                    hostname: record.hostname,
+                    ip_address: record.ip_address,
+                    group: record.group,
+                    tags: record.tags.split(';').map(str::to_string).collect(),
+                },
+            )),
+            Err(err) => errors.push(LineError {
+                line,
+                message: match err.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                    _ => err.to_string(),
+                },
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(InventoryError::Invalid(errors));
+    }
+    Ok(records)
+}
+
+fn parse_yaml(content: &str) -> Result<Vec<(usize, RawRecord)>, InventoryError> {
+    let values: Vec<serde_yaml::Value> = serde_yaml::from_str(content).map_err(|err| {
+        InventoryError::Invalid(vec![LineError {
+            line: err.location().map_or(1, |l| l.line()),
+            message: err.to_string(),
+        }])
+    })?;
+
+    // Top level entries of a sequence start with `- ` at the beginning of a line
+    let lines = entry_lines(content, values.len(), |l| {
+        l.starts_with('-') && !l.starts_with("---")
+    });
+
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    for (idx, value) in values.iter().enumerate() {
+        match serde_yaml::from_value::<RawRecord>(value.clone()) {
+            Ok(record) => records.push((lines[idx], record)),
+            Err(err) => errors.push(LineError {
+                line: lines[idx],
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(InventoryError::Invalid(errors));
+    }
+    Ok(records)
+}
+
+fn parse_toml(content: &str) -> Result<Vec<(usize, RawRecord)>, InventoryError> {
+    let inventory: RawTomlInventory = toml::from_str(content).map_err(|err| {
+        InventoryError::Invalid(vec![LineError {
+            line: err.span().map_or(1, |span| line_at(content, span.start)),
+            message: err.message().to_string(),
+        }])
+    })?;
+
+    let lines = entry_lines(content, inventory.hosts.len(), |l| l.trim() == "[[hosts]]");
+
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    for (idx, value) in inventory.hosts.into_iter().enumerate() {
+        match value.try_into::<RawRecord>() {
+            Ok(record) => records.push((lines[idx], record)),
+            Err(err) => errors.push(LineError {
+                line: lines[idx],
+                message: err.message().to_string(),
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(InventoryError::Invalid(errors));
+    }
+    Ok(records)
+}
+
+/// Finds lines on which entries start. If they can't be matched with parsed entries
+/// (e.g. document uses flow style), falls back to entries ordinals.
+fn entry_lines(content: &str, count: usize, is_entry_start: impl Fn(&str) -> bool) -> Vec<usize> {
+    let starts: Vec<usize> = content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| is_entry_start(l))
+        .map(|(idx, _)| idx + 1)
+        .collect();
+    if starts.len() == count {
+        starts
+    } else {
+        (1..=count).collect()
+    }
+}
+
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+fn validate(raw: RawRecord) -> Result<InventoryRecord, String> {
+    let hostname = raw.hostname.trim().to_string();
+    if hostname.is_empty() {
+        return Err("hostname is empty".into());
+    }
+    if hostname.contains(char::is_whitespace) {
+        return Err(format!("hostname '{hostname}' contains whitespace"));
+    }
+    let ip_address = raw
+        .ip_address
+        .trim()
+        .parse::<IpAddr>()
+        .map_err(|_| format!("'{}' is not a valid IP address", raw.ip_address.trim()))?;
+    let group = raw.group.trim().to_string();
+    if group.is_empty() {
+        return Err("group is empty".into());
+    }
+    if group.split('/').any(|segment| segment.trim().is_empty()) {
+        return Err(format!("group path '{group}' has an empty segment"));
+    }
+    let group = group.split('/').map(str::trim).join("/");
+    // root isn't part of paths of its descendants, `root/perf` is the same group as `perf`
+    let group = match group.strip_prefix("root/") {
+        Some(path) => path.to_string(),
+        None => group,
+    };
+    let tags = raw
+        .tags
+        .iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .sorted()
+        .dedup()
+        .collect();
+
+    Ok(InventoryRecord {
+        hostname,
+        ip_address,
+        group,
+        tags,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostUpdate {
+    pub host_id: HostId,
+    pub before: InventoryRecord,
+    pub after: InventoryRecord,
+    /// Host was retired and is brought back
+    pub restore: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HostRetirement {
+    pub host_id: HostId,
+    pub record: InventoryRecord,
+    /// Leased hosts are retired without ending the lease
+    pub leased: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct InventoryDiff {
+    pub new_groups: Vec<String>,
+    pub created: Vec<InventoryRecord>,
+    pub updated: Vec<HostUpdate>,
+    pub retired: Vec<HostRetirement>,
+    pub unchanged: usize,
+}
+
+impl InventoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.new_groups.is_empty()
+            && self.created.is_empty()
+            && self.updated.is_empty()
+            && self.retired.is_empty()
+    }
+}
+
+impl Display for InventoryDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for group in &self.new_groups {
+            writeln!(f, "+ group {group}")?;
+        }
+        for host in &self.created {
+            writeln!(f, "+ host {host}")?;
+        }
+        for HostUpdate {
+            before,
+            after,
+            restore,
+            ..
+        } in &self.updated
+        {
+            let mut changes = vec![];
+            if *restore {
+                changes.push("restored".to_string());
+            }
+            if before.ip_address != after.ip_address {
+                changes.push(format!(
+                    "ip_address {} -> {}",
+                    before.ip_address, after.ip_address
+                ));
+            }
+            if before.group != after.group {
+                changes.push(format!("group {} -> {}", before.group, after.group));
+            }
+            if before.tags != after.tags {
+                changes.push(format!(
+                    "tags [{}] -> [{}]",
+                    before.tags.join(", "),
+                    after.tags.join(", ")
+                ));
+            }
+            writeln!(f, "~ host {}: {}", after.hostname, changes.join(", "))?;
+        }
+        for HostRetirement { record, leased, .. } in &self.retired {
+            if *leased {
+                writeln!(f, "- host {record} (leased, kept until released)")?;
+            } else {
+                writeln!(f, "- host {record}")?;
+            }
+        }
+        write!(
+            f,
+            "{} groups to create, {} hosts to create, {} hosts to update, {} hosts to retire, {} unchanged",
+            self.new_groups.len(),
+            self.created.len(),
+            self.updated.len(),
+            self.retired.len(),
+            self.unchanged
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct InventoryService {
+    registry: Registry,
+}
+
+impl InventoryService {
+    pub fn new(registry: Registry) -> Self {
+        InventoryService { registry }
+    }
+
+    pub async fn export(&self) -> Result<Vec<InventoryRecord>, InventoryError> {
+        let mut tx = self.registry.begin().await?;
+        let records = current_inventory(&mut tx)
+            .await?
+            .into_iter()
+            .filter(|host| !host.retired)
+            .map(|host| host.record)
+            .collect();
+        tx.commit().await?;
+        Ok(records)
+    }
+
+    /// Creates missing groups and hosts and updates existing hosts (matched by hostname).
+    /// Hosts absent from `records` are left untouched, as are leases of updated hosts.
+    /// With `dry_run` nothing is written, only the diff is computed.
+    pub async fn import(
+        &self,
+        records: &[InventoryRecord],
+        dry_run: bool,
+    ) -> Result<InventoryDiff, InventoryError> {
+        self.reconcile(records, false, dry_run).await
+    }
+
+    /// Same as [`InventoryService::import`], but also retires hosts absent from `records`.
+    /// Retired hosts can't be leased anymore, active leases on them are kept until released.
+    pub async fn sync(
+        &self,
+        records: &[InventoryRecord],
+        dry_run: bool,
+    ) -> Result<InventoryDiff, InventoryError> {
+        self.reconcile(records, true, dry_run).await
+    }
+
+    async fn reconcile(
+        &self,
+        records: &[InventoryRecord],
+        retire_missing: bool,
+        dry_run: bool,
+    ) -> Result<InventoryDiff, InventoryError> {
+        let mut tx = self.registry.begin().await?;
+        let current = current_inventory(&mut tx).await?;
+        let mut groups: HashMap<String, GroupId> = groups_tree(tx.get_groups().await?)
+            .into_iter()
+            .map(|node| (node.path, node.group.id))
+            .collect();
+
+        let diff = compute_diff(&current, &groups, records, retire_missing);
+        if dry_run {
+            return Ok(diff);
+        }
+
+        // Parents go before their children in `new_groups`
+        for path in &diff.new_groups {
+            let (parent_id, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (Some(groups[parent]), name),
+                None => (None, path.as_str()),
+            };
+            let group_id = tx.add_group(name, parent_id.as_ref()).await?;
+            groups.insert(path.clone(), group_id);
+        }
+        for record in &diff.created {
+            tx.add_host(
+                &record.hostname,
+                IpNetwork::from(record.ip_address),
+                &groups[&record.group],
+                &record.tags,
+            )
+            .await?;
+        }
+        for HostUpdate { host_id, after, .. } in &diff.updated {
+            tx.update_host(
+                host_id,
+                IpNetwork::from(after.ip_address),
+                &groups[&after.group],
+                &after.tags,
+            )
+            .await?;
+        }
+        let retired: Vec<_> = diff.retired.iter().map(|h| h.host_id).collect();
+        tx.retire_hosts(&retired).await?;
+        tx.commit().await?;
+
+        Ok(diff)
+    }
+}
+
+/// Reconciles inventory file from `settings` with the database on every SIGHUP.
+/// Stops when `shutdown` is cancelled, a running sync is finished first.
+pub async fn inventory_sync_on_sighup(
+    service: InventoryService,
+    settings: InventorySettings,
+    shutdown: CancellationToken,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to listen for SIGHUP: {err}");
+            return shutdown.cancelled().await;
+        }
+    };
+    loop {
+        select! {
+            signal = hangup.recv() => if signal.is_none() { break },
+            _ = shutdown.cancelled() => break,
+        }
+        info!(
+            "Got SIGHUP, syncing inventory from {}",
+            settings.path.display()
+        );
+        match sync_inventory_file(&service, &settings).await {
+            Ok(diff) => info!("Inventory synced:\n{diff}"),
+            Err(err) => error!("Inventory sync failed: {err:#}"),
+        }
+    }
+}
+
+pub async fn sync_inventory_file(
+    service: &InventoryService,
+    settings: &InventorySettings,
+) -> anyhow::Result<InventoryDiff> {
+    let format = InventoryFormat::from_path(&settings.path)
+        .with_context(|| format!("Can't infer format of {}", settings.path.display()))?;
+    let content = tokio::fs::read_to_string(&settings.path)
+        .await
+        .with_context(|| format!("Failed to read {}", settings.path.display()))?;
+    Ok(service.sync(&format.parse(&content)?, false).await?)
+}
+
+struct InventoryHost {
+    host_id: HostId,
+    record: InventoryRecord,
+    retired: bool,
+    leased: bool,
+}
+
+async fn current_inventory(tx: &mut RegistryTx<'_>) -> sqlx::Result<Vec<InventoryHost>> {
+    let groups: HashMap<GroupId, String> = groups_tree(tx.get_groups().await?)
+        .into_iter()
+        .map(|node| (node.group.id, node.path))
+        .collect();
+
+    Ok(tx
+        .get_inventory_hosts()
+        .await?
+        .into_iter()
+        .map(|host| InventoryHost {
+            host_id: host.id,
+            retired: host.retired_at.is_some(),
+            leased: host.user_id.is_some(),
+            record: InventoryRecord {
+                hostname: host.hostname,
+                ip_address: host.ip_address.ip(),
+                group: groups.get(&host.group_id).cloned().unwrap_or_default(),
+                tags: host.tags,
+            },
+        })
+        .collect())
+}
+
+fn compute_diff(
+    current: &[InventoryHost],
+    groups: &HashMap<String, GroupId>,
+    records: &[InventoryRecord],
+    retire_missing: bool,
+) -> InventoryDiff {
+    let mut by_hostname: HashMap<&str, &InventoryHost> = HashMap::new();
+    for host in current {
+        by_hostname
+            .entry(host.record.hostname.as_str())
+            .or_insert(host);
+    }
+
+    let mut diff = InventoryDiff {
+        new_groups: records
+            .iter()
+            .flat_map(|r| {
+                // every ancestor of the group has to exist too
+                r.group
+                    .match_indices('/')
+                    .map(|(idx, _)| r.group[..idx].to_string())
+                    .chain([r.group.clone()])
+            })
+            .filter(|g| !groups.contains_key(g))
+            .unique()
+            .sorted_by_key(|g| g.matches('/').count())
+            .collect(),
+        ..Default::default()
+    };
+
+    for record in records {
+        match by_hostname.remove(record.hostname.as_str()) {
+            None => diff.created.push(record.clone()),
+            Some(host) if &host.record == record && !host.retired => diff.unchanged += 1,
+            Some(host) => diff.updated.push(HostUpdate {
+                host_id: host.host_id,
+                before: host.record.clone(),
+                after: record.clone(),
+                restore: host.retired,
+            }),
+        }
+    }
+
+    if retire_missing {
+        diff.retired = current
+            .iter()
+            .filter(|host| !host.retired)
+            .filter(|host| by_hostname.contains_key(host.record.hostname.as_str()))
+            .map(|host| HostRetirement {
+                host_id: host.host_id,
+                record: host.record.clone(),
+                leased: host.leased,
+            })
+            .collect();
+    }
+    diff
+}
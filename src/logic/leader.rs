@@ -0,0 +1,103 @@
+use std::{future::Future, time::Duration};
+
+use tokio::{select, time::sleep};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::{
+    db::{AdvisoryLock, Registry},
+    metrics::METRICS,
+};
+
+/// Advisory lock held by the instance running background jobs
+pub const LEADER_LOCK: i64 = 0x7461_6368_696b_6f6d;
+
+/// How often the leader checks its lock and followers try to take it
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Elects one of the instances sharing the database to run background jobs, like the release timer.
+/// The leader holds a Postgres advisory lock, once its connection is lost another instance takes over.
+#[derive(Clone)]
+pub struct LeaderElection {
+    registry: Registry,
+    interval: Duration,
+}
+
+impl LeaderElection {
+    pub fn new(registry: Registry, interval: Duration) -> Self {
+        Self { registry, interval }
+    }
+
+    /// Runs `job` while this instance is the leader until `shutdown` is cancelled.
+    /// The token given to `job` is cancelled on shutdown or when leadership is lost,
+    /// `job` is started again the next time this instance is elected.
+    pub async fn run<F, Fut>(&self, shutdown: CancellationToken, mut job: F)
+    where
+        F: FnMut(CancellationToken) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        // the same connection is used for every try until it's lost
+        let mut lock = None;
+        while !shutdown.is_cancelled() {
+            if let Err(err) = self.lead(&mut lock, &shutdown, &mut job).await {
+                error!("Leader election failed: {err}");
+                lock = None;
+            }
+            select! {
+                _ = sleep(self.interval) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        }
+        if let Some(lock) = lock
+            && let Err(err) = lock.close().await
+        {
+            warn!("Failed to close leader lock connection: {err}");
+        }
+    }
+
+    /// Tries to take the lock and runs `job` while it's held
+    async fn lead<F, Fut>(
+        &self,
+        lock: &mut Option<AdvisoryLock>,
+        shutdown: &CancellationToken,
+        job: &mut F,
+    ) -> sqlx::Result<()>
+    where
+        F: FnMut(CancellationToken) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let lock = match lock {
+            Some(lock) => lock,
+            None => lock.insert(self.registry.advisory_lock(LEADER_LOCK).await?),
+        };
+        if !lock.try_acquire().await? {
+            return Ok(());
+        }
+        info!("Elected as leader, starting background jobs");
+        METRICS.leader.set(1);
+        let leading = shutdown.child_token();
+        let (kept, _) = tokio::join!(self.keep(lock, leading.clone()), async {
+            job(leading.clone()).await;
+            leading.cancel();
+        });
+        METRICS.leader.set(0);
+        info!("Background jobs stopped");
+        kept?;
+        lock.release().await
+    }
+
+    /// Checks the lock until `leading` is cancelled, cancels it if the lock is lost
+    async fn keep(&self, lock: &mut AdvisoryLock, leading: CancellationToken) -> sqlx::Result<()> {
+        loop {
+            select! {
+                _ = sleep(self.interval) => {}
+                _ = leading.cancelled() => return Ok(()),
+            }
+            if let Err(err) = lock.check().await {
+                warn!("Lost leader lock");
+                leading.cancel();
+                return Err(err);
+            }
+        }
+    }
+}
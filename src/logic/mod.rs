@@ -1,6 +1,12 @@
+pub mod events;
 pub mod groups;
 pub mod hosts;
+pub mod inventory;
+pub mod leader;
 pub mod message_senders;
 pub mod notifications;
+pub mod policies;
 pub mod release;
+pub mod reports;
+pub mod throttling;
 pub mod users;
@@ -6,6 +6,7 @@ use crate::db::{
     Registry,
     models::{HostId, User, UserId},
 };
+use crate::metrics::METRICS;
 
 #[derive(Debug, Clone)]
 pub enum Notification {
@@ -13,6 +14,16 @@ pub enum Notification {
     ExpirationSoon(Vec<HostId>),
 }
 
+impl Notification {
+    /// Label of the notification in metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Notification::HostsReleased(_) => "hosts_released",
+            Notification::ExpirationSoon(_) => "expiration_soon",
+        }
+    }
+}
+
 #[async_trait]
 pub trait SendMessage {
     async fn send_message(&self, msg: String) -> Result<()>;
@@ -39,6 +50,16 @@ where
     }
 
     pub async fn notify(&self, user_id: UserId, notification: &Notification) -> Result<()> {
+        let result = self.send(user_id, notification).await;
+        let status = if result.is_ok() { "success" } else { "failure" };
+        METRICS
+            .notifications
+            .with_label_values(&[notification.kind(), status])
+            .inc();
+        result
+    }
+
+    async fn send(&self, user_id: UserId, notification: &Notification) -> Result<()> {
         let mut tx = self
             .registry
             .begin()
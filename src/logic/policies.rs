@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use chrono::TimeDelta;
+
+use super::groups::groups_tree;
+use crate::db::{
+    RegistryTx,
+    models::{GroupId, GroupLeasePolicy},
+};
+
+/// Policy of a group with values inherited from its ancestors
+#[derive(Clone, Debug, Default)]
+pub struct LeasePolicy {
+    pub max_lease: Option<TimeDelta>,
+    pub default_lease: Option<TimeDelta>,
+    /// Limit of hosts user can lease in the group which defines it (including its descendants)
+    pub user_limit: Option<(GroupId, usize)>,
+    /// AD groups allowed to see and lease hosts, `None` means everyone
+    pub allowed_ad_groups: Option<Vec<String>>,
+}
+
+impl LeasePolicy {
+    pub fn allows(&self, user_groups: &[String]) -> bool {
+        match &self.allowed_ad_groups {
+            None => true,
+            Some(allowed) => user_groups.iter().any(|g| allowed.contains(g)),
+        }
+    }
+}
+
+pub struct LeasePolicies {
+    parents: HashMap<GroupId, Option<GroupId>>,
+    paths: HashMap<GroupId, String>,
+    policies: HashMap<GroupId, GroupLeasePolicy>,
+    access: HashMap<GroupId, Vec<String>>,
+}
+
+impl LeasePolicies {
+    pub async fn load(tx: &mut RegistryTx<'_>) -> sqlx::Result<Self> {
+        let tree = groups_tree(tx.get_groups().await?);
+        let policies = tx
+            .get_group_lease_policies()
+            .await?
+            .into_iter()
+            .map(|p| (p.group_id, p))
+            .collect();
+        let mut access: HashMap<GroupId, Vec<String>> = HashMap::new();
+        for row in tx.get_groups_access().await? {
+            access.entry(row.group_id).or_default().push(row.ad_group);
+        }
+
+        Ok(Self {
+            parents: tree
+                .iter()
+                .map(|node| (node.group.id, node.group.parent_id))
+                .collect(),
+            paths: tree
+                .into_iter()
+                .map(|node| (node.group.id, node.path))
+                .collect(),
+            policies,
+            access,
+        })
+    }
+
+    pub fn path(&self, group_id: &GroupId) -> String {
+        self.paths
+            .get(group_id)
+            .cloned()
+            .unwrap_or_else(|| group_id.to_string())
+    }
+
+    /// Every field is taken from the closest group which sets it
+    pub fn effective(&self, group_id: &GroupId) -> LeasePolicy {
+        let mut effective = LeasePolicy::default();
+        for group_id in self.ancestors(group_id) {
+            if effective.allowed_ad_groups.is_none() {
+                effective.allowed_ad_groups = self.access.get(&group_id).cloned();
+            }
+            let Some(policy) = self.policies.get(&group_id) else {
+                continue;
+            };
+            let hours = |h: Option<i32>| h.map(|h| TimeDelta::hours(h.into()));
+            effective.max_lease = effective.max_lease.or(hours(policy.max_lease_hours));
+            effective.default_lease = effective
+                .default_lease
+                .or(hours(policy.default_lease_hours));
+            effective.user_limit = effective.user_limit.or(policy
+                .user_limit
+                .map(|limit| (group_id, limit.try_into().unwrap_or(0))));
+        }
+        effective
+    }
+
+    /// Access is restricted by the closest group with ACL entries
+    pub fn is_accessible(&self, group_id: &GroupId, user_groups: &[String]) -> bool {
+        self.effective(group_id).allows(user_groups)
+    }
+
+    /// Group itself and all of its descendants
+    pub fn subtree(&self, group_id: &GroupId) -> Vec<GroupId> {
+        self.parents
+            .keys()
+            .filter(|id| self.ancestors(id).contains(group_id))
+            .copied()
+            .collect()
+    }
+
+    /// Group itself followed by its parent, grandparent and so on
+    pub fn ancestors(&self, group_id: &GroupId) -> Vec<GroupId> {
+        let mut ancestors = vec![*group_id];
+        while let Some(Some(parent)) = self.parents.get(ancestors.last().unwrap()) {
+            // cycles can only appear by manual edits of the database
+            if ancestors.contains(parent) {
+                break;
+            }
+            ancestors.push(*parent);
+        }
+        ancestors
+    }
+}
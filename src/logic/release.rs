@@ -5,19 +5,39 @@ use std::{
 
 use chrono::{DateTime, Utc};
 
-use tokio::time::sleep;
+use sqlx::postgres::PgListener;
+use tokio::{select, time::sleep};
+use tokio_util::sync::CancellationToken;
 
 use crate::db::{
-    Registry,
+    LEASES_CHANNEL, Registry,
     models::{HostId, LeasedHost, UserId},
 };
+use crate::metrics::METRICS;
 use anyhow::Result;
 
+use super::events::HostsEvent;
 use super::notifications::{GetMessageSender, Notification, Notifier};
 use itertools::Itertools;
 use tracing::{debug, error};
 
-pub async fn hosts_release_timer<T: GetMessageSender>(registry: Registry, notifier: &Notifier<T>) {
+/// Longest sleep between checks, in case a notification was missed
+const MAX_WAIT: Duration = Duration::from_secs(5 * 60);
+
+/// Releases expired leases and reminds about expiring ones when they are due until `shutdown` is cancelled,
+/// the current run is finished. Changes of leases wake it up to recompute the next deadline.
+pub async fn hosts_release_timer<T: GetMessageSender>(
+    registry: Registry,
+    notifier: &Notifier<T>,
+    shutdown: CancellationToken,
+) {
+    let mut listener = match registry.listen(LEASES_CHANNEL).await {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            error!("Failed to listen for lease changes, checking every {MAX_WAIT:?}: {err}");
+            None
+        }
+    };
     let mut release_timer = ReleaseTimer {
         registry,
         last_expiration_soon_notification: HashMap::new(),
@@ -47,7 +67,36 @@ pub async fn hosts_release_timer<T: GetMessageSender>(registry: Registry, notifi
         if let Err(err) = release_timer.notify_soon_release(notifier).await {
             error!("Notify soon release fail: {err}");
         }
-        sleep(Duration::from_secs(10)).await;
+
+        let wait = match release_timer.next_deadline().await {
+            Ok(Some(deadline)) => (deadline - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+                .min(MAX_WAIT),
+            Ok(None) => MAX_WAIT,
+            Err(err) => {
+                error!("Failed to get next lease deadline: {err}");
+                MAX_WAIT
+            }
+        };
+        debug!("Next release check in {wait:?}");
+        select! {
+            _ = sleep(wait) => {}
+            _ = leases_changed(&mut listener) => debug!("Leases changed"),
+            _ = shutdown.cancelled() => break,
+        }
+    }
+}
+
+/// Waits for a notification on the leases channel, never resolves without a listener
+async fn leases_changed(listener: &mut Option<PgListener>) {
+    let Some(listener) = listener else {
+        return std::future::pending().await;
+    };
+    // `None` means the connection was lost and notifications could be missed
+    if let Err(err) = listener.try_recv().await {
+        error!("Failed to receive lease changes: {err}");
+        sleep(Duration::from_secs(1)).await;
     }
 }
 
@@ -71,11 +120,28 @@ impl ReleaseTimer {
                     .as_ref(),
             )
             .await?;
+            HostsEvent::Released(expired_hosts.iter().map(|h| h.id).collect())
+                .notify(&mut tx)
+                .await?;
             tx.commit().await?;
+            METRICS.expired_leases.inc_by(expired_hosts.len() as u64);
         }
         Ok(expired_hosts)
     }
 
+    /// When the next lease expires or the next reminder is due
+    async fn next_deadline(&self) -> Result<Option<DateTime<Utc>>> {
+        let now = Utc::now();
+        let mut tx = self.registry.begin().await?;
+        let expiry = tx.get_next_lease_end(now).await?;
+        let reminder = tx
+            .get_next_lease_end(now + self.expiration_notify_delay_time)
+            .await?
+            .map(|end| end - self.expiration_notify_delay_time);
+        tx.commit().await?;
+        Ok(expiry.into_iter().chain(reminder).min())
+    }
+
     async fn notify_released_hosts<T: GetMessageSender>(
         &mut self,
         notifier: &Notifier<T>,
@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::Serialize;
+use thiserror::Error;
+
+use super::policies::LeasePolicies;
+use crate::db::{
+    Registry,
+    models::{Host, LeaseRecord},
+};
+
+/// Number of users listed in [`UtilisationReport::top_users`]
+pub const TOP_USERS: usize = 10;
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Database error")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Failed to render report: {0}")]
+    Render(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupUtilisation {
+    pub group: String,
+    pub hosts: usize,
+    /// Share of host time leased on every day of the period, from 0 to 1
+    pub daily: Vec<f64>,
+    /// Share of host time leased over the whole period
+    pub utilisation: f64,
+    /// Most hosts of the group leased at the same time
+    pub peak: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserUsage {
+    pub email: String,
+    pub leases: usize,
+    /// Time hosts were leased by the user within the period
+    pub leased_time: TimeDelta,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdleHost {
+    pub hostname: String,
+    pub ip_address: String,
+    pub group: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UtilisationReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub days: Vec<NaiveDate>,
+    pub groups: Vec<GroupUtilisation>,
+    /// Most hosts leased at the same time
+    pub peak: usize,
+    /// Leases started within the period
+    pub leases: usize,
+    /// Average length of leases started within the period, open leases count up to `to`
+    pub average_lease: Option<TimeDelta>,
+    pub top_users: Vec<UserUsage>,
+    /// Hosts which weren't leased at all within the period
+    pub idle_hosts: Vec<IdleHost>,
+}
+
+/// Lease of a host as a row of the exported CSV
+#[derive(Serialize)]
+struct CsvLease {
+    hostname: String,
+    ip_address: String,
+    group: String,
+    user: String,
+    leased_at: DateTime<Utc>,
+    leased_until: DateTime<Utc>,
+    released_at: Option<DateTime<Utc>>,
+    hours: String,
+    note: Option<String>,
+    ticket: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ReportsService {
+    registry: Registry,
+}
+
+impl ReportsService {
+    pub fn new(registry: Registry) -> Self {
+        ReportsService { registry }
+    }
+
+    /// Utilisation of hosts between `from` and `to` based on lease history.
+    /// Hosts are counted in their current groups.
+    pub async fn report(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<UtilisationReport, ReportError> {
+        let mut tx = self.registry.begin().await?;
+        let records = tx.get_lease_history(from, to).await?;
+        let hosts = tx.get_inventory_hosts().await?;
+        let policies = LeasePolicies::load(&mut tx).await?;
+        tx.commit().await?;
+
+        let hosts: Vec<_> = hosts
+            .into_iter()
+            .filter(|h| h.retired_at.is_none_or(|retired| retired > from))
+            .collect();
+        Ok(build_report(from, to, &records, &hosts, &policies))
+    }
+
+    /// Leases overlapping with the period as CSV
+    pub async fn history_csv(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<String, ReportError> {
+        let mut tx = self.registry.begin().await?;
+        let records = tx.get_lease_history(from, to).await?;
+        let policies = LeasePolicies::load(&mut tx).await?;
+        tx.commit().await?;
+
+        let now = Utc::now();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for record in records {
+            let length =
+                record.released_at.unwrap_or(now.min(record.leased_until)) - record.leased_at;
+            writer
+                .serialize(CsvLease {
+                    hostname: record.hostname,
+                    ip_address: record.ip_address.ip().to_string(),
+                    group: policies.path(&record.group_id),
+                    user: record.email,
+                    leased_at: record.leased_at,
+                    leased_until: record.leased_until,
+                    released_at: record.released_at,
+                    hours: format!("{:.2}", hours(length)),
+                    note: record.note.note,
+                    ticket: record.note.ticket,
+                })
+                .map_err(|e| ReportError::Render(e.to_string()))?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| ReportError::Render(e.to_string()))?;
+        String::from_utf8(bytes).map_err(|e| ReportError::Render(e.to_string()))
+    }
+}
+
+fn hours(duration: TimeDelta) -> f64 {
+    duration.num_seconds() as f64 / 3600.0
+}
+
+/// Part of the lease within `from..to`, `None` if they don't overlap
+fn clip(
+    record: &LeaseRecord,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = record.leased_at.max(from);
+    let end = record.released_at.unwrap_or(record.leased_until).min(to);
+    (start < end).then_some((start, end))
+}
+
+/// Most intervals overlapping at the same moment
+fn peak(intervals: &[(DateTime<Utc>, DateTime<Utc>)]) -> usize {
+    let mut events: Vec<_> = intervals
+        .iter()
+        .flat_map(|(start, end)| [(*start, 1), (*end, -1)])
+        .collect();
+    // lease released at the moment another one starts doesn't overlap with it
+    events.sort();
+    let (mut current, mut peak) = (0i64, 0i64);
+    for (_, change) in events {
+        current += change;
+        peak = peak.max(current);
+    }
+    peak as usize
+}
+
+/// Calendar days (UTC) of the period, the first and the last ones may be partial
+fn days(from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<(NaiveDate, DateTime<Utc>, DateTime<Utc>)> {
+    let mut days = vec![];
+    let mut day = from.date_naive();
+    while day.and_time(Default::default()).and_utc() < to {
+        let start = day.and_time(Default::default()).and_utc().max(from);
+        let next = day + TimeDelta::days(1);
+        let end = next.and_time(Default::default()).and_utc().min(to);
+        days.push((day, start, end));
+        day = next;
+    }
+    days
+}
+
+fn overlap(
+    intervals: &[(DateTime<Utc>, DateTime<Utc>)],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> TimeDelta {
+    intervals
+        .iter()
+        .map(|(start, end)| (*end).min(to) - (*start).max(from))
+        .filter(|d| *d > TimeDelta::zero())
+        .sum()
+}
+
+fn share(leased: TimeDelta, hosts: usize, period: TimeDelta) -> f64 {
+    let total = hours(period) * hosts as f64;
+    if total > 0.0 {
+        (hours(leased) / total).min(1.0)
+    } else {
+        0.0
+    }
+}
+
+fn build_report(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    records: &[LeaseRecord],
+    hosts: &[Host],
+    policies: &LeasePolicies,
+) -> UtilisationReport {
+    let days = days(from, to);
+
+    let mut hosts_by_group: HashMap<String, usize> = HashMap::new();
+    for host in hosts {
+        *hosts_by_group
+            .entry(policies.path(&host.group_id))
+            .or_default() += 1;
+    }
+    let mut intervals_by_group: HashMap<String, Vec<_>> = HashMap::new();
+    let mut users: HashMap<&str, UserUsage> = HashMap::new();
+    let mut leased_hosts = HashSet::new();
+    for record in records {
+        let Some(interval) = clip(record, from, to) else {
+            continue;
+        };
+        leased_hosts.insert(record.host_id);
+        intervals_by_group
+            .entry(policies.path(&record.group_id))
+            .or_default()
+            .push(interval);
+        let usage = users.entry(&record.email).or_insert_with(|| UserUsage {
+            email: record.email.clone(),
+            leases: 0,
+            leased_time: TimeDelta::zero(),
+        });
+        usage.leases += 1;
+        usage.leased_time += interval.1 - interval.0;
+    }
+
+    let group_names: HashSet<_> = hosts_by_group
+        .keys()
+        .chain(intervals_by_group.keys())
+        .cloned()
+        .collect();
+    let mut groups: Vec<_> = group_names
+        .into_iter()
+        .map(|group| {
+            let intervals = intervals_by_group.remove(&group).unwrap_or_default();
+            let hosts = hosts_by_group.get(&group).copied().unwrap_or_default();
+            GroupUtilisation {
+                daily: days
+                    .iter()
+                    .map(|(_, start, end)| {
+                        share(overlap(&intervals, *start, *end), hosts, *end - *start)
+                    })
+                    .collect(),
+                utilisation: share(overlap(&intervals, from, to), hosts, to - from),
+                peak: peak(&intervals),
+                group,
+                hosts,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.group.cmp(&b.group));
+
+    let all_intervals: Vec<_> = records.iter().filter_map(|r| clip(r, from, to)).collect();
+    let started: Vec<_> = records
+        .iter()
+        .filter(|r| r.leased_at >= from)
+        .map(|r| r.released_at.unwrap_or(r.leased_until).min(to) - r.leased_at)
+        .collect();
+    let average_lease =
+        (!started.is_empty()).then(|| started.iter().sum::<TimeDelta>() / started.len() as i32);
+
+    let mut top_users: Vec<_> = users.into_values().collect();
+    top_users.sort_by(|a, b| {
+        b.leased_time
+            .cmp(&a.leased_time)
+            .then_with(|| a.email.cmp(&b.email))
+    });
+    top_users.truncate(TOP_USERS);
+
+    let mut idle_hosts: Vec<_> = hosts
+        .iter()
+        .filter(|h| h.retired_at.is_none() && !leased_hosts.contains(&h.id))
+        .map(|h| IdleHost {
+            hostname: h.hostname.clone(),
+            ip_address: h.ip_address.ip().to_string(),
+            group: policies.path(&h.group_id),
+        })
+        .collect();
+    idle_hosts.sort_by(|a, b| (&a.group, &a.hostname).cmp(&(&b.group, &b.hostname)));
+
+    UtilisationReport {
+        from,
+        to,
+        days: days.into_iter().map(|(day, _, _)| day).collect(),
+        groups,
+        peak: peak(&all_intervals),
+        leases: started.len(),
+        average_lease,
+        top_users,
+        idle_hosts,
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, TimeDelta, Utc};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{configuration::LoginThrottleSettings, db::Registry};
+
+#[derive(Error, Debug)]
+pub enum ThrottleError {
+    #[error("Database error")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Login is locked until {0}")]
+    Locked(DateTime<Utc>),
+}
+
+/// Locks logins by username and by client address after too many failed attempts.
+/// Every failure over the limit doubles lockout period.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    registry: Registry,
+    settings: LoginThrottleSettings,
+}
+
+fn attempt_keys(username: &str, ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+    let mut keys = vec![("username", username.trim().to_lowercase())];
+    if let Some(ip) = ip {
+        keys.push(("ip", ip.to_string()));
+    }
+    keys
+}
+
+impl LoginThrottle {
+    pub fn new(registry: Registry, settings: LoginThrottleSettings) -> Self {
+        Self { registry, settings }
+    }
+
+    /// Address of the client, or the one reported by reverse proxy if it is trusted
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            // the last address is added by our proxy, the rest are set by the client
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        match forwarded {
+            Some(ip) if self.settings.trust_forwarded_for => ip,
+            _ => peer.ip(),
+        }
+    }
+
+    pub async fn check(&self, username: &str, ip: Option<IpAddr>) -> Result<(), ThrottleError> {
+        let mut tx = self.registry.begin().await?;
+        for (kind, value) in attempt_keys(username, ip) {
+            if let Some(until) = tx.get_login_lock(kind, &value).await?
+                && until > Utc::now()
+            {
+                return Err(ThrottleError::Locked(until));
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the end of lockout if it was applied
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<DateTime<Utc>>, ThrottleError> {
+        let window = TimeDelta::seconds(self.settings.window_secs);
+        let mut locked = None;
+        let now = Utc::now();
+        let mut tx = self.registry.begin().await?;
+        for (kind, value) in attempt_keys(username, ip) {
+            let failures = tx.add_login_failure(kind, &value, window).await?;
+            let Some(lockout) = self.lockout(failures) else {
+                continue;
+            };
+            let until = now + lockout;
+            tx.set_login_lock(kind, &value, until).await?;
+            warn!(
+                kind,
+                value,
+                failures,
+                ip = ip.map(|ip| ip.to_string()),
+                "Login is locked until {until} after {failures} failed attempts"
+            );
+            locked = locked.max(Some(until));
+        }
+        tx.commit().await?;
+        Ok(locked)
+    }
+
+    /// Resets failures of the username, failures of the address are kept
+    pub async fn record_success(&self, username: &str) -> Result<(), ThrottleError> {
+        let mut tx = self.registry.begin().await?;
+        for (kind, value) in attempt_keys(username, None) {
+            tx.clear_login_failures(kind, &value).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    fn lockout(&self, failures: i32) -> Option<TimeDelta> {
+        let over_limit = u32::try_from(failures - self.settings.max_failures).ok()?;
+        let lockout = self
+            .settings
+            .lockout_secs
+            .saturating_mul(1_i64.checked_shl(over_limit).unwrap_or(i64::MAX))
+            .min(self.settings.max_lockout_secs);
+        Some(TimeDelta::seconds(lockout))
+    }
+}
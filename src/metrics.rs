@@ -0,0 +1,115 @@
+use std::{sync::LazyLock, time::Duration};
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Collectors of the application, exposed on `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    /// Hosts by group path and state (`free`, `leased` or `retired`), refreshed on every scrape
+    pub hosts: IntGaugeVec,
+    /// Users holding hosts by group path, refreshed on every scrape
+    pub leasing_users: IntGaugeVec,
+    /// Lease periods requested by users, in hours
+    pub lease_duration: Histogram,
+    /// Hosts released by the release timer after their lease expired
+    pub expired_leases: IntCounter,
+    /// Notifications by kind and result (`success` or `failure`)
+    pub notifications: IntCounterVec,
+    /// Duration of LDAP requests by operation (`bind` or `search`)
+    pub ldap_request_duration: HistogramVec,
+    /// Lookups of the user info cache by result (`hit`, `miss` or `stale_hit`)
+    pub ldap_cache: IntCounterVec,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    /// 1 while this instance holds the leader lock and runs background jobs
+    pub leader: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("tachikoma".into()), None).unwrap();
+        let metrics = Self {
+            hosts: IntGaugeVec::new(
+                Opts::new("hosts", "Hosts by group and state"),
+                &["group", "state"],
+            )
+            .unwrap(),
+            leasing_users: IntGaugeVec::new(
+                Opts::new("leasing_users", "Users holding hosts by group"),
+                &["group"],
+            )
+            .unwrap(),
+            lease_duration: Histogram::with_opts(
+                HistogramOpts::new("lease_duration_hours", "Requested lease periods").buckets(
+                    vec![1.0, 2.0, 4.0, 8.0, 24.0, 72.0, 168.0, 336.0, 720.0, 1488.0],
+                ),
+            )
+            .unwrap(),
+            expired_leases: IntCounter::new("expired_leases_total", "Hosts released on expiration")
+                .unwrap(),
+            notifications: IntCounterVec::new(
+                Opts::new("notifications_total", "Sent notifications"),
+                &["kind", "result"],
+            )
+            .unwrap(),
+            ldap_request_duration: HistogramVec::new(
+                HistogramOpts::new("ldap_request_duration_seconds", "LDAP requests duration"),
+                &["operation"],
+            )
+            .unwrap(),
+            ldap_cache: IntCounterVec::new(
+                Opts::new("ldap_cache_lookups_total", "User info cache lookups"),
+                &["result"],
+            )
+            .unwrap(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests"),
+                &["method", "path", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP requests duration"),
+                &["method", "path"],
+            )
+            .unwrap(),
+            leader: IntGauge::new("leader", "Instance runs background jobs").unwrap(),
+            registry,
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.hosts.clone()),
+            Box::new(metrics.leasing_users.clone()),
+            Box::new(metrics.lease_duration.clone()),
+            Box::new(metrics.expired_leases.clone()),
+            Box::new(metrics.notifications.clone()),
+            Box::new(metrics.ldap_request_duration.clone()),
+            Box::new(metrics.ldap_cache.clone()),
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.leader.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Metrics in Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    pub fn observe_ldap_request(&self, operation: &str, duration: Duration) {
+        self.ldap_request_duration
+            .with_label_values(&[operation])
+            .observe(duration.as_secs_f64());
+    }
+}
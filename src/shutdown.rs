@@ -0,0 +1,25 @@
+use tokio::{
+    select,
+    signal::unix::{SignalKind, signal},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+/// Cancels `shutdown` on the first SIGTERM or SIGINT
+pub async fn cancel_on_signal(shutdown: CancellationToken) {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => terminate.recv().await,
+            Err(err) => {
+                error!("Failed to listen for SIGTERM: {err}");
+                std::future::pending().await
+            }
+        }
+    };
+    select! {
+        _ = terminate => info!("Got SIGTERM, shutting down"),
+        _ = tokio::signal::ctrl_c() => info!("Got SIGINT, shutting down"),
+        _ = shutdown.cancelled() => return,
+    }
+    shutdown.cancel();
+}
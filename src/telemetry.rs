@@ -1,18 +1,188 @@
-use tracing_subscriber::fmt::format::FmtSpan;
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use opentelemetry::{KeyValue, global, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+};
+use serde_json::{Map, Value};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+    warn,
+};
+use tracing_subscriber::fmt::{
+    FmtContext, FormatEvent, FormattedFields,
+    format::{FmtSpan, JsonFields, Writer},
+};
 use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{EnvFilter, fmt};
 
-pub fn init_tracing() {
-    let mut fmt_layer = fmt::layer();
-    if std::env::var("INCLUDE_SPAN_EVENTS").is_ok_and(|value| value.eq_ignore_ascii_case("true")) {
-        fmt_layer = fmt_layer.with_span_events(FmtSpan::ENTER | FmtSpan::EXIT);
+use crate::{
+    AppInfo,
+    configuration::{LogFormat, Settings, TelemetrySettings},
+};
+
+/// Flushes exported spans when dropped, must be kept until exit
+pub struct TracingGuard {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take()
+            && let Err(err) = provider.shutdown()
+        {
+            warn!("Failed to flush traces: {err}");
+        }
     }
+}
+
+pub fn init_tracing(settings: &Settings) -> anyhow::Result<TracingGuard> {
     let filter_layer = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))
         .unwrap();
+    let fmt_layer = match settings.log.format {
+        LogFormat::Text => {
+            let mut fmt_layer = fmt::layer();
+            if std::env::var("INCLUDE_SPAN_EVENTS")
+                .is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+            {
+                fmt_layer = fmt_layer.with_span_events(FmtSpan::ENTER | FmtSpan::EXIT);
+            }
+            fmt_layer.with_filter(filter_layer).boxed()
+        }
+        LogFormat::Json => json_layer().with_filter(filter_layer).boxed(),
+    };
+
+    // spans are exported with their own filter, so debug spans don't end up in the log
+    let (otel_layer, provider) = match &settings.telemetry {
+        Some(telemetry) => {
+            let provider = tracer_provider(telemetry)?;
+            let filter = EnvFilter::try_new(&telemetry.filter)
+                .with_context(|| format!("Invalid telemetry filter '{}'", telemetry.filter))?;
+            let layer = tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer("tachikoma"))
+                .with_filter(filter);
+            global::set_text_map_propagator(TraceContextPropagator::new());
+            (Some(layer), Some(provider))
+        }
+        None => (None, None),
+    };
 
     tracing_subscriber::registry()
-        .with(filter_layer)
         .with(fmt_layer)
+        .with(otel_layer)
         .init();
+    Ok(TracingGuard { provider })
+}
+
+/// Log layer which writes [`JsonFormat`] lines to stdout
+pub fn json_layer<S>() -> fmt::Layer<S, JsonFields, JsonFormat>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fmt::layer()
+        .fmt_fields(JsonFields::new())
+        .event_format(JsonFormat)
+}
+
+/// Formats an event as a JSON object with `timestamp`, `level`, `target`, `spans` and fields of
+/// the event and all its spans at the top level, e.g. `request_id` and `username` of HTTP requests.
+/// Fields of inner spans and of the event override the outer ones.
+pub struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut object = Map::new();
+        object.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        object.insert("level".into(), metadata.level().as_str().into());
+        object.insert("target".into(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = vec![];
+            for span in scope.from_root() {
+                spans.push(span.name());
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<JsonFields>>()
+                    && let Ok(Value::Object(fields)) = serde_json::from_str(fields)
+                {
+                    object.extend(fields);
+                }
+            }
+            object.insert("spans".into(), spans.join(":").into());
+        }
+
+        event.record(&mut JsonVisitor(&mut object));
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+/// Provider which sends spans to the OTLP/HTTP collector in batches
+pub fn tracer_provider(telemetry: &TelemetrySettings) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!(
+            "{}/v1/traces",
+            telemetry.otlp_endpoint.trim_end_matches('/')
+        ))
+        .build()
+        .context("Failed to build OTLP exporter")?;
+    let resource = Resource::builder()
+        .with_service_name(telemetry.service_name.clone())
+        .with_attribute(KeyValue::new("service.version", AppInfo::new().version))
+        .build();
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            telemetry.sample_ratio,
+        ))))
+        .build())
 }
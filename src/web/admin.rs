@@ -0,0 +1,74 @@
+use axum::{
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::error;
+
+use super::auth::roles::{AdminRole, Authorized};
+use crate::logic::inventory::{InventoryError, InventoryFormat, InventoryService};
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub format: Option<InventoryFormat>,
+}
+
+pub async fn export_inventory(
+    _: Authorized<AdminRole>,
+    State(service): State<InventoryService>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let format = params.format.unwrap_or(InventoryFormat::Csv);
+    let rendered = match service.export().await {
+        Ok(records) => format.render(&records),
+        Err(err) => Err(err),
+    };
+    match rendered {
+        Ok(content) => (
+            [
+                (header::CONTENT_TYPE, format.content_type().to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"inventory.{}\"", format.extension()),
+                ),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Failed to export inventory: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    pub format: Option<InventoryFormat>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+pub async fn import_inventory(
+    _: Authorized<AdminRole>,
+    State(service): State<InventoryService>,
+    Query(params): Query<ImportParams>,
+    body: String,
+) -> Response {
+    let format = params.format.unwrap_or(InventoryFormat::Csv);
+    let diff = match format.parse(&body) {
+        Ok(records) => service.import(&records, params.dry_run).await,
+        Err(err) => Err(err),
+    };
+    match diff {
+        Ok(diff) => (StatusCode::OK, diff.to_string()).into_response(),
+        Err(err @ InventoryError::Invalid(_)) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response()
+        }
+        Err(err) => {
+            error!("Failed to import inventory: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
@@ -1,16 +1,22 @@
 use askama::Template;
+use std::net::SocketAddr;
+
 use axum::{
     Form,
+    extract::{ConnectInfo, State},
+    http::HeaderMap,
     response::{Html, IntoResponse, Redirect, Response},
 };
 use axum_flash::{Flash, IncomingFlashes};
 use secrecy::SecretString;
 use serde::Deserialize;
-use tracing::error;
 use tracing::warn;
+use tracing::{error, info};
 
 use crate::{
     AppInfo,
+    auth::OidcClient,
+    logic::throttling::{LoginThrottle, ThrottleError},
     web::{
         auth::middleware::{AuthSession, Credentials},
         flash_redirect,
@@ -23,25 +29,53 @@ pub struct FormData {
     password: SecretString,
 }
 
+/// Same for wrong credentials and lockouts, so lockouts don't reveal existing usernames
+const LOGIN_FAILED: &str = "Wrong credentials or too many failed attempts, try again later";
+
 #[tracing::instrument(
-    skip(form, flash, session),
+    skip(form, flash, session, throttle, headers),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
     mut session: AuthSession,
+    State(throttle): State<LoginThrottle>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     flash: Flash,
     Form(form): Form<FormData>,
 ) -> axum::response::Result<Redirect> {
-    let credentials = Credentials {
+    tracing::Span::current().record("username", tracing::field::display(&form.username));
+    let ip = throttle.client_ip(addr, &headers);
+    let username = form.username.clone();
+    match throttle.check(&username, Some(ip)).await {
+        Ok(()) => {}
+        Err(ThrottleError::Locked(until)) => {
+            info!("Login attempt from {ip} while locked until {until}");
+            return Err(flash_redirect(LOGIN_FAILED, "/login", flash));
+        }
+        Err(err) => {
+            warn!("Failed to check login throttling: {}", err);
+            return Err(flash_redirect("Something went wrong", "/login", flash));
+        }
+    }
+
+    let credentials = Credentials::Password {
         username: form.username,
         password: form.password,
     };
-    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     let user = match session.authenticate(credentials).await {
-        Ok(Some(user)) => user,
+        Ok(Some(user)) => {
+            if let Err(err) = throttle.record_success(&username).await {
+                warn!("Failed to reset failed logins: {}", err);
+            }
+            user
+        }
         Ok(None) => {
-            return Err(flash_redirect("Wrong credentials", "/login", flash));
+            if let Err(err) = throttle.record_failure(&username, Some(ip)).await {
+                warn!("Failed to record failed login: {}", err);
+            }
+            return Err(flash_redirect(LOGIN_FAILED, "/login", flash));
         }
         Err(e) => {
             warn!("Authentication error: {}", e);
@@ -70,15 +104,20 @@ pub async fn logout(mut session: AuthSession) -> axum::response::Result<Redirect
 #[template(path = "login.html", escape = "none")]
 struct LoginPage {
     error: Option<String>,
+    oidc_enabled: bool,
     app_info: AppInfo,
 }
 
 #[tracing::instrument(skip_all)]
-pub async fn login_page(flashes: IncomingFlashes) -> Response {
+pub async fn login_page(
+    State(oidc): State<Option<OidcClient>>,
+    flashes: IncomingFlashes,
+) -> Response {
     let error = flashes.into_iter().next().map(|(_, text)| text.to_string());
     let resp = Html(
         LoginPage {
             error,
+            oidc_enabled: oidc.is_some(),
             app_info: AppInfo::new(),
         }
         .to_string(),
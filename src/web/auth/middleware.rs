@@ -1,27 +1,31 @@
+use std::sync::Arc;
+
+use crate::auth::{AuthProvider, OidcClient};
+use crate::configuration::RolesSettings;
 use crate::db::Registry;
-use crate::db::models::User as DbUser;
-use crate::ldap::UsersInfo;
+use crate::db::models::{Role, User as DbUser};
+use crate::ldap::AdUserInfo;
 use anyhow::Context;
 use axum::response::IntoResponse;
 use axum::response::{Redirect, Response};
 use axum::{async_trait, extract::Request, middleware::Next};
 use axum_login::{AuthUser, AuthnBackend, UserId};
 use secrecy::SecretString;
-use tracing::info;
 
 #[derive(Debug, Clone)]
 pub struct User {
     id: i32,
     pub username: String,
     pub groups: Vec<String>,
+    pub role: Role,
     pub tg_handle: Option<String>,
     pub link: String,
     session_token: Vec<u8>,
 }
 
-impl From<(DbUser, Vec<String>)> for User {
-    fn from(value: (DbUser, Vec<String>)) -> Self {
-        let (user, groups) = value;
+impl From<(DbUser, Vec<String>, Role)> for User {
+    fn from(value: (DbUser, Vec<String>, Role)) -> Self {
+        let (user, groups, role) = value;
         Self {
             id: *user.id,
             username: user.email,
@@ -29,6 +33,7 @@ impl From<(DbUser, Vec<String>)> for User {
             link: user.link.clone(),
             session_token: user.link.into_bytes(),
             groups,
+            role,
         }
     }
 }
@@ -47,23 +52,57 @@ impl AuthUser for User {
 
 #[derive(Clone)]
 pub struct Backend {
-    users_info: UsersInfo,
+    auth_provider: Arc<dyn AuthProvider>,
+    oidc: Option<OidcClient>,
+    roles: RolesSettings,
     registry: Registry,
 }
 
 impl Backend {
-    pub fn new(registry: Registry, users_info: UsersInfo) -> Self {
+    pub fn new(
+        registry: Registry,
+        auth_provider: Arc<dyn AuthProvider>,
+        oidc: Option<OidcClient>,
+        roles: RolesSettings,
+    ) -> Self {
         Backend {
-            users_info,
+            auth_provider,
+            oidc,
+            roles,
             registry,
         }
     }
+
+    /// Role is derived from current groups of the user
+    fn with_role(&self, user: DbUser, groups: Vec<String>) -> User {
+        let role = self.roles.role_of(&groups);
+        (user, groups, role).into()
+    }
+
+    /// Users created by OIDC logins are kept in the database, the rest come from `auth_provider`.
+    /// Claims of the last OIDC login are used for users `auth_provider` doesn't know.
+    async fn user_info(&self, dn: &str) -> anyhow::Result<Option<AdUserInfo>> {
+        let Some(oidc) = &self.oidc else {
+            return self.auth_provider.get_user_info(dn).await;
+        };
+        if OidcClient::is_oidc_user(dn) {
+            return oidc.get_user_info(dn).await;
+        }
+        match self.auth_provider.get_user_info(dn).await? {
+            Some(info) => Ok(Some(info)),
+            None => oidc.get_user_info(dn).await,
+        }
+    }
 }
 
 #[derive(Clone)]
-pub struct Credentials {
-    pub username: String,
-    pub password: SecretString,
+pub enum Credentials {
+    Password {
+        username: String,
+        password: SecretString,
+    },
+    /// User info from the ID token of a finished OIDC login
+    Oidc(AdUserInfo),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -82,22 +121,22 @@ impl AuthnBackend for Backend {
 
     async fn authenticate(
         &self,
-        Credentials { username, password }: Credentials,
+        credentials: Credentials,
     ) -> Result<Option<Self::User>, Self::Error> {
-        let Some(u_info) = self.users_info.find_user_info(username.clone()).await? else {
-            info!("Authentication failed for '{}': unknown user", username);
-            return Ok(None);
+        let u_info = match credentials {
+            Credentials::Password { username, password } => {
+                let Some(u_info) = self
+                    .auth_provider
+                    .authenticate(&username, &password)
+                    .await?
+                else {
+                    return Ok(None);
+                };
+                u_info
+            }
+            Credentials::Oidc(u_info) => u_info,
         };
 
-        let resp = self
-            .users_info
-            .check_authentication(&u_info.dn, &password)
-            .await;
-
-        if let Err(err) = resp {
-            info!("Authentication failed for '{}': '{}'", username, err);
-            return Ok(None);
-        }
         let user = self
             .registry
             .begin()
@@ -114,7 +153,12 @@ impl AuthnBackend for Backend {
                 user.unwrap()
             }
         };
-        Ok(Some((user, u_info.groups).into()))
+        let user = self.with_role(user, u_info.groups);
+        // saved on login only, so requests don't write to the database
+        let mut tx = self.registry.begin().await?;
+        tx.set_user_role(&user.id.into(), user.role).await?;
+        tx.commit().await?;
+        Ok(Some(user))
     }
 
     async fn get_user(&self, user_id: &UserId<Self>) -> Result<Option<Self::User>, Self::Error> {
@@ -131,12 +175,11 @@ impl AuthnBackend for Backend {
         };
 
         let u_info = self
-            .users_info
-            .get_user_info(&user.dn)
+            .user_info(&user.dn)
             .await?
             .with_context(|| format!("Missed user info '{}' ({})", user.dn, user.email))?;
 
-        Ok(Some((user, u_info.groups).into()))
+        Ok(Some(self.with_role(user, u_info.groups)))
     }
 }
 
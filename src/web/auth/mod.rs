@@ -1,2 +1,4 @@
 pub mod login;
 pub mod middleware;
+pub mod oidc;
+pub mod roles;
@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+};
+use axum_flash::Flash;
+use serde::Deserialize;
+use tower_sessions::Session;
+use tracing::{error, warn};
+
+use crate::{
+    auth::{OidcClient, OidcFlow},
+    web::{
+        auth::middleware::{AuthSession, Credentials},
+        flash_redirect,
+    },
+};
+
+const FLOW_KEY: &str = "oidc_flow";
+
+#[tracing::instrument(skip_all)]
+pub async fn oidc_login(
+    State(oidc): State<Option<OidcClient>>,
+    session: Session,
+    flash: Flash,
+) -> axum::response::Result<Redirect> {
+    let Some(oidc) = oidc else {
+        return Ok(Redirect::to("/login"));
+    };
+    let (url, flow) = oidc.start().await.map_err(|err| {
+        error!("Failed to start OIDC login: {err:#}");
+        flash_redirect("SSO is unavailable", "/login", flash.clone())
+    })?;
+    session.insert(FLOW_KEY, flow).await.map_err(|err| {
+        error!("Failed to save OIDC login: {err}");
+        flash_redirect("Something went wrong", "/login", flash.clone())
+    })?;
+    Ok(Redirect::to(&url))
+}
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn oidc_callback(
+    State(oidc): State<Option<OidcClient>>,
+    mut auth_session: AuthSession,
+    session: Session,
+    flash: Flash,
+    Query(params): Query<CallbackParams>,
+) -> axum::response::Result<Redirect> {
+    let Some(oidc) = oidc else {
+        return Ok(Redirect::to("/login"));
+    };
+    let flow: Option<OidcFlow> = session.remove(FLOW_KEY).await.unwrap_or_else(|err| {
+        error!("Failed to load OIDC login: {err}");
+        None
+    });
+    let (Some(flow), Some(code)) = (flow, params.code) else {
+        warn!("OIDC login failed: {:?}", params.error);
+        return Err(flash_redirect("SSO login failed", "/login", flash));
+    };
+    if params.state.as_ref() != Some(&flow.state) {
+        warn!("OIDC login failed: state doesn't match");
+        return Err(flash_redirect("SSO login failed", "/login", flash));
+    }
+
+    let info = oidc.finish(&flow, &code).await.map_err(|err| {
+        warn!("OIDC login failed: {err:#}");
+        flash_redirect("SSO login failed", "/login", flash.clone())
+    })?;
+    tracing::Span::current().record("username", tracing::field::display(&info.email));
+
+    let user = match auth_session.authenticate(Credentials::Oidc(info)).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(flash_redirect("SSO login failed", "/login", flash)),
+        Err(err) => {
+            warn!("Authentication error: {}", err);
+            return Err(flash_redirect("Something went wrong", "/login", flash));
+        }
+    };
+    auth_session.login(&user).await.map_err(|err| {
+        error!("Got unexpected error: {}", err);
+        "Unexpected error".to_string()
+    })?;
+    Ok(Redirect::to("/hosts"))
+}
@@ -0,0 +1,62 @@
+use std::marker::PhantomData;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use super::middleware::User;
+use crate::db::models::Role;
+
+/// Marker of the least role required by a handler
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct ViewerRole;
+pub struct UserRole;
+pub struct AdminRole;
+
+impl RequiredRole for ViewerRole {
+    const ROLE: Role = Role::Viewer;
+}
+impl RequiredRole for UserRole {
+    const ROLE: Role = Role::User;
+}
+impl RequiredRole for AdminRole {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Logged in user with at least `R` role, requests of other users are rejected with 403.
+/// Must be used behind `auth_middleware`.
+pub struct Authorized<R: RequiredRole>(pub User, pub PhantomData<R>);
+
+#[async_trait]
+impl<S, R> FromRequestParts<S> for Authorized<R>
+where
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(user) = parts.extensions.get::<User>().cloned() else {
+            return Err(StatusCode::UNAUTHORIZED.into_response());
+        };
+        if user.role < R::ROLE {
+            warn!(
+                "User {} with role {} was denied access to {}",
+                user.username, user.role, parts.uri
+            );
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("{} role is required", R::ROLE),
+            )
+                .into_response());
+        }
+        Ok(Self(user, PhantomData))
+    }
+}
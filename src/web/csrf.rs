@@ -0,0 +1,94 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{Method, StatusCode, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_sessions::Session;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const CSRF_FIELD: &str = "csrf_token";
+const SESSION_KEY: &str = "csrf_token";
+/// Forms are small, larger bodies are only accepted with the token in the header
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// Token of the current session, added to request extensions by [`csrf_middleware`]
+#[derive(Clone, Debug)]
+pub struct CsrfToken(pub String);
+
+fn tokens_match(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn form_token(body: &[u8]) -> Option<String> {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body)
+        .ok()?
+        .into_iter()
+        .find_map(|(key, value)| (key == CSRF_FIELD).then_some(value))
+}
+
+/// Keeps a token in the session and checks it on every request changing state.
+/// Token is taken from `X-CSRF-Token` header (set for all htmx requests) or `csrf_token` form field.
+pub async fn csrf_middleware(session: Session, request: Request, next: Next) -> Response {
+    let token = match session.get::<String>(SESSION_KEY).await {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+            if let Err(err) = session.insert(SESSION_KEY, &token).await {
+                error!("Failed to save CSRF token: {err}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            token
+        }
+        Err(err) => {
+            error!("Failed to load CSRF token: {err}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut request = request;
+    if !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        let header = request
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let is_form = request.headers().get(CONTENT_TYPE).is_some_and(|value| {
+            value
+                .as_bytes()
+                .starts_with(b"application/x-www-form-urlencoded")
+        });
+
+        let provided = match header {
+            Some(header) => Some(header),
+            None if is_form => {
+                let (parts, body) = request.into_parts();
+                let Ok(body) = to_bytes(body, MAX_FORM_SIZE).await else {
+                    return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+                };
+                let provided = form_token(&body);
+                request = Request::from_parts(parts, Body::from(body));
+                provided
+            }
+            None => None,
+        };
+        if !provided.is_some_and(|provided| tokens_match(&token, &provided)) {
+            warn!("Request to {} rejected: invalid CSRF token", request.uri());
+            return (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response();
+        }
+    }
+
+    request.extensions_mut().insert(CsrfToken(token));
+    next.run(request).await
+}
@@ -0,0 +1,28 @@
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use tracing::warn;
+
+use crate::health::{HealthChecks, Status};
+
+/// Liveness, the server is up and handles requests
+pub async fn healthz() -> Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+/// Readiness, all dependencies are available
+pub async fn readyz(State(checks): State<HealthChecks>) -> Response {
+    let readiness = checks.readiness().await;
+    let status = match readiness.status {
+        Status::Ok => StatusCode::OK,
+        Status::Fail => {
+            warn!("Not ready: {:?}", readiness.checks);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    (status, Json(readiness)).into_response()
+}
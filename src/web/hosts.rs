@@ -2,7 +2,10 @@ use askama::Template;
 use axum::{
     Extension,
     extract::{Query, State},
-    response::{Html, IntoResponse, Json, Redirect},
+    response::{
+        Html, IntoResponse, Json, Redirect,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
 use axum_extra::extract::{CookieJar, Form, OptionalQuery};
 use axum_flash::{Flash, IncomingFlashes};
@@ -10,50 +13,66 @@ use axum_login::AuthUser;
 use chrono::TimeDelta;
 use std::{collections::HashMap, ops::Deref};
 
-use serde::Deserialize;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Deserializer};
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
 
 use super::templates::{AllHostsPage, HostInfo, HostsLeasePage, HostsPage};
 use crate::{AppInfo, logic::users::UsersService};
 use crate::{db::models::UserId, logic::hosts::HostsService};
 use crate::{
-    db::models::{GroupId, HostId, User as UserDb},
-    logic::groups::GroupsService,
+    db::models::{GroupId, HostId, LeaseNote, Role, User as UserDb},
+    logic::{groups::GroupsService, policies::LeasePolicy},
 };
 
-use super::auth::middleware::User;
-use super::{AuthLink, flash_redirect};
+use super::auth::roles::{Authorized, UserRole, ViewerRole};
+use super::csrf::CsrfToken;
+use super::{AuthLink, ShuttingDown, flash_redirect};
 use axum_extra::extract::cookie::Cookie;
 #[derive(Deserialize)]
 pub struct HostsParams {
     pub group_id: Option<GroupId>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn get_hosts(
     params: Query<HostsParams>,
     State(hosts_service): State<HostsService>,
     State(groups_service): State<GroupsService>,
     State(AuthLink(auth_link)): State<AuthLink>,
     flashes: IncomingFlashes,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<ViewerRole>,
+    Extension(CsrfToken(csrf_token)): Extension<CsrfToken>,
     jar: CookieJar,
 ) -> impl IntoResponse {
-    let groups = groups_service.get_all_groups().await.unwrap();
-    let group_id = params.group_id.unwrap_or_else(|| {
-        jar.get("group_id").map_or_else(
-            || groups[0].id,
-            |cookie| cookie.value().parse::<GroupId>().unwrap_or(groups[0].id),
-        )
+    let groups = groups_service
+        .get_accessible_groups_tree(&user.groups)
+        .await
+        .unwrap();
+    let group_id = params.group_id.or_else(|| {
+        jar.get("group_id")
+            .and_then(|cookie| cookie.value().parse::<GroupId>().ok())
     });
 
+    // user may have no access to any group at all
     let selected_group = groups
         .iter()
-        .find(|group| group.id == group_id)
-        .unwrap_or(&groups[0])
-        .clone();
-    let hosts = hosts_service
-        .get_available_group_hosts(&selected_group.id)
-        .await
-        .unwrap();
+        .find(|node| Some(node.group.id) == group_id)
+        .or(groups.first())
+        .cloned();
+    let (hosts, policy) = match &selected_group {
+        Some(group) => (
+            hosts_service
+                .get_available_group_hosts(&group.group.id, &user.groups)
+                .await
+                .unwrap(),
+            hosts_service
+                .get_group_policy(&group.group.id)
+                .await
+                .unwrap(),
+        ),
+        None => (vec![], LeasePolicy::default()),
+    };
 
     let leased = hosts_service
         .get_leased_hosts(&user.id().into())
@@ -62,30 +81,35 @@ pub async fn get_hosts(
 
     let error = flashes.into_iter().next().map(|(_, err)| err.to_owned());
     let lease_page = HostsLeasePage {
+        policy: policy.into(),
         groups: groups.into_iter().map(|g| g.into()).collect(),
-        selected_group: selected_group.into(),
+        selected_group: selected_group.map(|g| g.into()),
         hosts: hosts.into_iter().map(|h| h.into()).collect(),
         leased: leased.into_iter().map(|h| h.into()).collect(),
         error,
+        can_lease: user.role >= Role::User,
+        csrf_token: csrf_token.clone(),
     };
     let page = HostsPage {
         user: user.into(),
         auth_link,
         page: lease_page,
         app_info: AppInfo::new(),
+        csrf_token,
     };
 
-    (
-        jar.add(Cookie::new("group_id", group_id.to_string())),
-        flashes,
-        Html(page.render().unwrap()),
-    )
+    let jar = match &page.page.selected_group {
+        Some(group) => jar.add(Cookie::new("group_id", group.id.to_string())),
+        None => jar,
+    };
+    (jar, flashes, Html(page.render().unwrap()))
 }
 pub async fn get_all_hosts(
     State(hosts_service): State<HostsService>,
     State(user_service): State<UsersService>,
     State(AuthLink(auth_link)): State<AuthLink>,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<ViewerRole>,
+    Extension(CsrfToken(csrf_token)): Extension<CsrfToken>,
 ) -> impl IntoResponse {
     let users: HashMap<UserId, UserDb> = user_service
         .get_all_users()
@@ -104,12 +128,14 @@ pub async fn get_all_hosts(
                 (h, user).into()
             })
             .collect(),
+        csrf_token: csrf_token.clone(),
     };
     let page = HostsPage {
         user: user.into(),
         auth_link,
         page: lease_page,
         app_info: AppInfo::new(),
+        csrf_token,
     };
 
     Html(page.render().unwrap())
@@ -131,10 +157,10 @@ impl TryFrom<String> for Days {
     type Error = String;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.parse::<u8>() {
-            Ok(v @ 0..=63) => Ok(Self(v as i64)),
-            Ok(_) => Err("Value must be between 0 and 63".to_string()),
-            Err(_) => Err(format!("Wrong value {value}, can not parse as u8")),
+        // upper bound is the maximum lease of the group or `MAX_LEASE_DURATION`, checked by `HostsService`
+        match value.parse::<u16>() {
+            Ok(v) => Ok(Self(v as i64)),
+            Err(_) => Err(format!("Wrong value {value}, can not parse as u16")),
         }
     }
 }
@@ -164,16 +190,61 @@ impl TryFrom<String> for Hours {
 }
 #[derive(Deserialize)]
 pub struct LeaseForm {
-    days: Days,
-    hours: Hours,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    days: Option<Days>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    hours: Option<Hours>,
     #[serde(default)]
     hosts_ids: Vec<HostId>,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    ticket: String,
+}
+
+impl LeaseForm {
+    fn note(&self) -> LeaseNote {
+        lease_note(&self.note, &self.ticket)
+    }
+
+    /// `None` if lease period is not set, so default of the group is used
+    fn lease_for(&self) -> Option<TimeDelta> {
+        if self.days.is_none() && self.hours.is_none() {
+            return None;
+        }
+        let days = self.days.as_ref().map_or(0, |d| **d);
+        let hours = self.hours.as_ref().map_or(0, |h| **h);
+        Some(TimeDelta::hours(hours + days * 24))
+    }
+}
+
+/// Blank fields of the forms mean no note or ticket
+fn lease_note(note: &str, ticket: &str) -> LeaseNote {
+    let non_empty = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    LeaseNote {
+        note: non_empty(note),
+        ticket: non_empty(ticket),
+    }
+}
+
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<String, Error = String>,
+{
+    let value = String::deserialize(deserializer)?;
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    T::try_from(value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
 pub async fn lease_hosts(
     State(service): State<HostsService>,
     flash: Flash,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<UserRole>,
     Form(data): Form<LeaseForm>,
 ) -> axum::response::Result<Redirect> {
     let res = service
@@ -181,7 +252,8 @@ pub async fn lease_hosts(
             &user.id().into(),
             &user.groups,
             &data.hosts_ids,
-            TimeDelta::hours(*data.hours + *data.days * 24),
+            data.lease_for(),
+            &data.note(),
         )
         .await;
     match res {
@@ -199,15 +271,16 @@ pub async fn lease_random(
     params: Query<LeaseRandomHostParams>,
     State(service): State<HostsService>,
     flash: Flash,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<UserRole>,
     Form(data): Form<LeaseForm>,
 ) -> axum::response::Result<Redirect> {
     let res = service
         .lease_random(
             &user.id().into(),
             &user.groups,
-            TimeDelta::hours(*data.hours + *data.days * 24),
+            data.lease_for(),
             &params.group_id,
+            &data.note(),
         )
         .await;
     match res {
@@ -223,7 +296,7 @@ pub struct ReleaseForm {
 
 pub async fn release_hosts(
     State(service): State<HostsService>,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<UserRole>,
     Form(data): Form<ReleaseForm>,
 ) -> impl IntoResponse {
     service
@@ -233,9 +306,36 @@ pub async fn release_hosts(
     Redirect::to("/hosts")
 }
 
+#[derive(Deserialize)]
+pub struct NoteForm {
+    #[serde(default)]
+    hosts_ids: Vec<HostId>,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    ticket: String,
+}
+
+/// Replaces the note of the selected leased hosts, blank fields clear it
+pub async fn set_note(
+    State(service): State<HostsService>,
+    flash: Flash,
+    Authorized(user, _): Authorized<UserRole>,
+    Form(data): Form<NoteForm>,
+) -> axum::response::Result<Redirect> {
+    let note = lease_note(&data.note, &data.ticket);
+    match service
+        .set_note(&user.id().into(), &data.hosts_ids, &note)
+        .await
+    {
+        Ok(()) => Ok(Redirect::to("/hosts")),
+        Err(e) => Err(flash_redirect(&e.to_string(), "/hosts", flash)),
+    }
+}
+
 pub async fn release_all(
     State(service): State<HostsService>,
-    Extension(user): Extension<User>,
+    Authorized(user, _): Authorized<UserRole>,
 ) -> impl IntoResponse {
     service.free_all(&user.id().into()).await.unwrap();
     Redirect::to("/hosts")
@@ -292,3 +392,25 @@ pub async fn get_hosts_json(
         }
     }
 }
+
+/// Name of server-sent events with leases, hosts pages refresh their lists on it
+const HOSTS_EVENT: &str = "hosts";
+
+/// Streams leases and releases of hosts as server-sent events
+pub async fn hosts_events(
+    State(hosts_service): State<HostsService>,
+    State(ShuttingDown(shutting_down)): State<ShuttingDown>,
+    _: Authorized<ViewerRole>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let events = BroadcastStream::new(hosts_service.events().subscribe())
+        .map(|event| {
+            let sse = Event::default().event(HOSTS_EVENT);
+            match event {
+                Ok(event) => sse.json_data(event),
+                // subscriber missed some events, refreshing the page is enough anyway
+                Err(BroadcastStreamRecvError::Lagged(_)) => Ok(sse.data("lagged")),
+            }
+        })
+        .take_until(shutting_down.cancelled_owned());
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
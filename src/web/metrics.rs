@@ -0,0 +1,46 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+
+use crate::{logic::hosts::HostsService, metrics::METRICS};
+
+pub async fn get_metrics(State(hosts_service): State<HostsService>) -> Response {
+    if let Err(err) = hosts_service.update_metrics().await {
+        error!("Failed to update hosts metrics: {:?}", err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+        .into_response()
+}
+
+/// Counts requests by route, unmatched paths are grouped together to keep labels bounded
+pub async fn metrics_middleware(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_owned();
+
+    let response = next.run(request).await;
+
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &path])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
@@ -1,5 +1,11 @@
+mod admin;
 mod auth;
+pub mod csrf;
+mod health;
 mod hosts;
+mod metrics;
+mod reports;
+mod sessions;
 mod templates;
 
 use axum::http::StatusCode;
@@ -16,46 +22,70 @@ use axum_extra::extract::cookie::Key;
 use axum_flash::Flash;
 use axum_login::AuthManagerLayerBuilder;
 use md5::{Digest, Md5};
-use std::{convert::Infallible, net::SocketAddr};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
 
 use self::auth::{
     login,
     middleware::{Backend, auth_middleware},
+    oidc,
 };
-use crate::ldap::UsersInfo;
+use self::csrf::csrf_middleware;
+use self::sessions::AppSessionStore;
 use crate::{
+    auth::{AuthProvider, OidcClient},
     configuration::Settings,
     db::Registry,
-    logic::{groups::GroupsService, hosts::HostsService, users::UsersService},
+    health::HealthChecks,
+    logic::{
+        events::HostsEvents, groups::GroupsService, hosts::HostsService,
+        inventory::InventoryService, reports::ReportsService, throttling::LoginThrottle,
+        users::UsersService,
+    },
 };
+use opentelemetry::global;
+use opentelemetry_http::HeaderExtractor;
+use tokio_util::sync::CancellationToken;
 use tower_http::trace::TraceLayer;
-use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer, cookie::time::Duration};
-use tracing::info;
+use tower_sessions::{Expiry, SessionManagerLayer, cookie::time::Duration};
+use tracing::{info, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
 #[derive(FromRef, Clone)]
 struct AppState {
     hosts_service: HostsService,
     groups_service: GroupsService,
     users_service: UsersService,
+    inventory_service: InventoryService,
+    reports_service: ReportsService,
+    oidc: Option<OidcClient>,
+    login_throttle: LoginThrottle,
+    health_checks: HealthChecks,
     flash_config: axum_flash::Config,
     auth_link: AuthLink,
+    shutting_down: ShuttingDown,
 }
 
 #[derive(Clone)]
 pub struct AuthLink(pub String);
 
+/// Cancelled once the server starts shutting down, streaming responses end on it
+#[derive(Clone)]
+pub struct ShuttingDown(pub CancellationToken);
+
 pub struct Application {
     listening_addr: SocketAddr,
     server: Server,
+    /// Serves `/metrics` apart from the application, so it isn't exposed with it
+    metrics_server: Option<(SocketAddr, Server)>,
+    health_checks: HealthChecks,
 }
 
 impl Application {
     pub async fn build(
         settings: &Settings,
         registry: Registry,
-        ldap: ldap3::Ldap,
-        authorized_ldap: ldap3::Ldap,
+        auth_provider: Arc<dyn AuthProvider>,
         auth_link: String,
     ) -> Result<Application, anyhow::Error> {
         let tracing_layer = TraceLayer::new_for_http().make_span_with(|req: &Request<Body>| {
@@ -63,17 +93,54 @@ impl Application {
             let uri = req.uri();
             let matched_path = req.extensions().get::<MatchedPath>().map(|p| p.as_str());
 
-            tracing::debug_span!("http-request", %method, %uri, matched_path, request_id = %Uuid::new_v4())
+            // continue the trace of the caller, e.g. a reverse proxy
+            let parent = global::get_text_map_propagator(|propagator| {
+                propagator.extract(&HeaderExtractor(req.headers()))
+            });
+            // user fields are recorded by auth_middleware
+            let span = tracing::info_span!(
+                "http-request",
+                %method,
+                %uri,
+                matched_path,
+                request_id = %Uuid::new_v4(),
+                username = tracing::field::Empty,
+                user_id = tracing::field::Empty,
+            );
+            span.set_parent(parent);
+            span
         });
 
-        let session_layer = SessionManagerLayer::new(MemoryStore::default())
+        let shutting_down = CancellationToken::new();
+        let session_store = AppSessionStore::new(
+            &settings.app.session_store,
+            &registry,
+            shutting_down.clone(),
+        );
+        let session_layer = SessionManagerLayer::new(session_store)
             .with_secure(true)
             .with_expiry(Expiry::OnInactivity(Duration::days(7)));
 
-        let users_info =
-            UsersInfo::new(ldap, authorized_ldap, settings.ldap.users_query.clone()).await?;
+        let hosts_events = HostsEvents::listen(&registry, shutting_down.clone()).await?;
+        let hosts_service =
+            HostsService::new(registry.clone(), settings.app.lease_limit).with_events(hosts_events);
+        let health_checks = HealthChecks::default();
+        health_checks.add("postgres", Arc::new(registry.clone()));
+        for (name, check) in auth_provider.health_checks() {
+            health_checks.add(name, check);
+        }
+
+        let oidc = settings
+            .oidc
+            .clone()
+            .map(|oidc| OidcClient::new(oidc, registry.clone()));
         let auth_layer = AuthManagerLayerBuilder::new(
-            Backend::new(registry.clone(), users_info.clone()),
+            Backend::new(
+                registry.clone(),
+                auth_provider,
+                oidc.clone(),
+                settings.roles.clone(),
+            ),
             session_layer,
         )
         .build();
@@ -105,53 +172,134 @@ impl Application {
             .route("/logout", get(login::logout))
             .route("/hosts", get(hosts::get_hosts))
             .route("/hosts/all", get(hosts::get_all_hosts))
+            .route("/hosts/events", get(hosts::hosts_events))
             .route("/hosts/lease", post(hosts::lease_hosts))
             .route("/hosts/lease/random", post(hosts::lease_random))
             .route("/hosts/release", post(hosts::release_hosts))
-            .route("/hosts/release/all", post(hosts::release_all));
+            .route("/hosts/release/all", post(hosts::release_all))
+            .route("/hosts/note", post(hosts::set_note))
+            .route("/reports", get(reports::get_reports))
+            .route("/reports/leases.csv", get(reports::export_leases))
+            .route("/admin/inventory", get(admin::export_inventory))
+            .route("/admin/inventory/import", post(admin::import_inventory));
 
         let app = Router::new()
             .route("/login", post(login::login).get(login::login_page))
+            .route("/login/oidc", get(oidc::oidc_login))
+            .route("/login/oidc/callback", get(oidc::oidc_callback))
             .route("/hosts/leased", get(hosts::get_hosts_json))
+            .route("/healthz", get(health::healthz))
+            .route("/readyz", get(health::readyz))
             .merge(assets_router)
-            .merge(authed_router.route_layer(middleware::from_fn(auth_middleware)))
+            .merge(
+                authed_router
+                    .route_layer(middleware::from_fn(csrf_middleware))
+                    .route_layer(middleware::from_fn(auth_middleware)),
+            )
             .fallback(|| async { Redirect::to("/hosts").into_response() })
             .layer(auth_layer)
+            .layer(middleware::from_fn(metrics::metrics_middleware))
             .layer(tracing_layer)
             .with_state(AppState {
-                hosts_service: HostsService::new(registry.clone(), settings.app.lease_limit),
+                hosts_service: hosts_service.clone(),
                 groups_service: GroupsService::new(registry.clone()),
-                users_service: UsersService::new(registry),
+                users_service: UsersService::new(registry.clone()),
+                inventory_service: InventoryService::new(registry.clone()),
+                reports_service: ReportsService::new(registry.clone()),
+                oidc,
+                login_throttle: LoginThrottle::new(registry, settings.login_throttle.clone()),
+                health_checks: health_checks.clone(),
                 flash_config: axum_flash::Config::new(Key::derive_from(&settings.app.hmac_secret)),
                 auth_link: AuthLink(auth_link),
+                shutting_down: ShuttingDown(shutting_down.clone()),
             });
 
+        let metrics_server = match &settings.metrics {
+            Some(metrics_settings) => {
+                let listener = TcpListener::bind(metrics_settings.socket_addr()).await?;
+                let app = Router::new()
+                    .route("/metrics", get(metrics::get_metrics))
+                    .with_state(hosts_service);
+                Some((
+                    listener.local_addr()?,
+                    Server::new(listener, app, shutting_down.clone()),
+                ))
+            }
+            None => None,
+        };
+
         let listener = TcpListener::bind(settings.app.socket_addr()).await?;
         Ok(Self {
             listening_addr: listener.local_addr()?,
-            server: Server::new(listener, app),
+            server: Server::new(listener, app, shutting_down),
+            metrics_server,
+            health_checks,
         })
     }
-    pub async fn serve_forever(self) -> Result<(), std::io::Error> {
+    /// Stops accepting connections once `shutdown` is cancelled and waits for in-flight requests
+    pub async fn serve_until(self, shutdown: CancellationToken) -> Result<(), std::io::Error> {
         info!("Web server is listening on {}", self.listening_addr);
-        self.server.serve().await
+        match self.metrics_server {
+            Some((addr, metrics)) => {
+                info!("Metrics are served on {addr}");
+                tokio::try_join!(self.server.serve(shutdown.clone()), metrics.serve(shutdown))?;
+                Ok(())
+            }
+            None => self.server.serve(shutdown).await,
+        }
     }
     pub fn listening_addr(&self) -> SocketAddr {
         self.listening_addr
     }
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_server.as_ref().map(|(addr, _)| *addr)
+    }
+    /// Checks of `/readyz`, components started outside of the server can add their own
+    pub fn health_checks(&self) -> HealthChecks {
+        self.health_checks.clone()
+    }
 }
 
+/// How long in-flight requests may run after shutdown before their connections are dropped
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 struct Server {
     listener: TcpListener,
     app: Router,
+    shutting_down: CancellationToken,
 }
 impl Server {
-    pub fn new(listener: TcpListener, app: Router) -> Self {
-        Self { listener, app }
+    pub fn new(listener: TcpListener, app: Router, shutting_down: CancellationToken) -> Self {
+        Self {
+            listener,
+            app,
+            shutting_down,
+        }
     }
 
-    pub async fn serve(self) -> Result<(), std::io::Error> {
-        axum::serve(self.listener, self.app).await
+    pub async fn serve(self, shutdown: CancellationToken) -> Result<(), std::io::Error> {
+        let serving = axum::serve(
+            self.listener,
+            self.app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown.cancelled().await;
+                // otherwise open event streams would keep the server running
+                self.shutting_down.cancel();
+            }
+        });
+        tokio::select! {
+            result = serving => result,
+            _ = async {
+                shutdown.cancelled().await;
+                tokio::time::sleep(SHUTDOWN_TIMEOUT).await;
+            } => {
+                warn!("Requests still running after {SHUTDOWN_TIMEOUT:?}, dropping them");
+                Ok(())
+            }
+        }
     }
 }
 
@@ -173,6 +321,7 @@ where
 
     let content_hash = format!("{:x}", hasher.finalize());
     get(move |request: axum::extract::Request| async move {
+        #[allow(clippy::collapsible_if)]
         if let Some(header_value) = request.headers().get(axum::http::header::IF_NONE_MATCH) {
             if header_value.to_str().unwrap_or("").eq(&content_hash) {
                 return StatusCode::NOT_MODIFIED.into_response();
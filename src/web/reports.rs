@@ -0,0 +1,90 @@
+use askama::Template;
+use axum::{
+    Extension,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tracing::error;
+
+use super::AuthLink;
+use super::auth::roles::{Authorized, ViewerRole};
+use super::csrf::CsrfToken;
+use super::templates::{HostsPage, ReportsPage};
+use crate::{AppInfo, logic::reports::ReportsService};
+
+const DEFAULT_DAYS: i64 = 30;
+const MAX_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+pub struct ReportParams {
+    pub days: Option<i64>,
+}
+
+impl ReportParams {
+    /// Last `days` calendar days including today
+    fn period(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let days = self.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+        let to = Utc::now();
+        let from = (to.date_naive() - TimeDelta::days(days - 1))
+            .and_time(Default::default())
+            .and_utc();
+        (from, to)
+    }
+}
+
+pub async fn get_reports(
+    State(service): State<ReportsService>,
+    State(AuthLink(auth_link)): State<AuthLink>,
+    Authorized(user, _): Authorized<ViewerRole>,
+    Extension(CsrfToken(csrf_token)): Extension<CsrfToken>,
+    Query(params): Query<ReportParams>,
+) -> Response {
+    let (from, to) = params.period();
+    let report = match service.report(from, to).await {
+        Ok(report) => report,
+        Err(err) => {
+            error!("Failed to build report: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response();
+        }
+    };
+    let page = HostsPage {
+        user: user.into(),
+        auth_link,
+        page: ReportsPage::new(report, (to.date_naive() - from.date_naive()).num_days() + 1),
+        app_info: AppInfo::new(),
+        csrf_token,
+    };
+    Html(page.render().unwrap()).into_response()
+}
+
+pub async fn export_leases(
+    State(service): State<ReportsService>,
+    _: Authorized<ViewerRole>,
+    Query(params): Query<ReportParams>,
+) -> Response {
+    let (from, to) = params.period();
+    match service.history_csv(from, to).await {
+        Ok(content) => (
+            [
+                (header::CONTENT_TYPE, "text/csv".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"leases-{}-{}.csv\"",
+                        from.date_naive(),
+                        to.date_naive()
+                    ),
+                ),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Failed to export leases: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
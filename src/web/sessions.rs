@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tower_sessions::{
+    ExpiredDeletion, MemoryStore, SessionStore,
+    session::{Id, Record},
+    session_store,
+};
+use tracing::error;
+
+use crate::{
+    configuration::SessionStoreKind,
+    db::{Registry, sessions::PgSessionStore},
+};
+
+/// How often expired sessions are removed from the database
+const CLEANUP_PERIOD: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Session store selected in configuration
+#[derive(Clone, Debug)]
+pub enum AppSessionStore {
+    Memory(MemoryStore),
+    Postgres(PgSessionStore),
+}
+
+impl AppSessionStore {
+    /// Creates the store, for Postgres also spawns a task deleting expired sessions until `shutdown` is cancelled
+    pub fn new(kind: &SessionStoreKind, registry: &Registry, shutdown: CancellationToken) -> Self {
+        match kind {
+            SessionStoreKind::Memory => Self::Memory(MemoryStore::default()),
+            SessionStoreKind::Postgres => {
+                let store = PgSessionStore::new(registry);
+                tokio::spawn(delete_expired_sessions(store.clone(), shutdown));
+                Self::Postgres(store)
+            }
+        }
+    }
+}
+
+async fn delete_expired_sessions(store: PgSessionStore, shutdown: CancellationToken) {
+    let mut interval = tokio::time::interval(CLEANUP_PERIOD);
+    loop {
+        select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+        if let Err(err) = store.delete_expired().await {
+            error!("Failed to delete expired sessions: {err}");
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for AppSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.create(record).await,
+            Self::Postgres(store) => store.create(record).await,
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.save(record).await,
+            Self::Postgres(store) => store.save(record).await,
+        }
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            Self::Memory(store) => store.load(session_id).await,
+            Self::Postgres(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match self {
+            Self::Memory(store) => store.delete(session_id).await,
+            Self::Postgres(store) => store.delete(session_id).await,
+        }
+    }
+}
@@ -5,7 +5,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     AppInfo,
-    db::models::{Group, GroupId, Host, HostId, LeasedHost, User as UserDb},
+    db::models::{GroupId, Host, HostId, LeaseNote, LeasedHost, Role, User as UserDb},
+    logic::{
+        groups::GroupNode,
+        hosts::{DEFAULT_LEASE_DURATION, MAX_LEASE_DURATION},
+        policies::LeasePolicy,
+        reports::{IdleHost, UtilisationReport},
+    },
 };
 
 use super::auth::middleware::User;
@@ -17,28 +23,109 @@ pub struct HostsPage<T: Template> {
     pub user: UserInfo,
     pub auth_link: String,
     pub app_info: AppInfo,
+    pub csrf_token: String,
 }
 
 #[derive(Template, Debug)]
 #[template(path = "hosts_lease.html", escape = "none")]
 pub struct HostsLeasePage {
     pub groups: Vec<GroupInfo>,
-    pub selected_group: GroupInfo,
+    pub selected_group: Option<GroupInfo>,
     pub hosts: Vec<HostInfo>,
     pub leased: Vec<HostInfo>,
+    pub policy: PolicyInfo,
     pub error: Option<String>,
+    /// Viewers can't lease or release hosts
+    pub can_lease: bool,
+    pub csrf_token: String,
 }
 
 #[derive(Template, Debug)]
 #[template(path = "all_hosts.html", escape = "none")]
 pub struct AllHostsPage {
     pub hosts: Vec<HostInfo>,
+    pub csrf_token: String,
+}
+
+#[derive(Template, Debug)]
+#[template(path = "reports.html", escape = "none")]
+pub struct ReportsPage {
+    pub days: i64,
+    pub from: String,
+    pub to: String,
+    pub peak: usize,
+    pub leases: usize,
+    pub average_lease: String,
+    pub day_labels: Vec<String>,
+    pub groups: Vec<GroupReport>,
+    pub top_users: Vec<UserReport>,
+    pub idle_hosts: Vec<IdleHost>,
+}
+
+#[derive(Debug)]
+pub struct GroupReport {
+    pub group: String,
+    pub hosts: usize,
+    pub utilisation: u32,
+    pub peak: usize,
+    /// Percent of leased host time by day
+    pub daily: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct UserReport {
+    pub email: String,
+    pub leases: usize,
+    pub leased_time: String,
+}
+
+impl ReportsPage {
+    pub fn new(report: UtilisationReport, days: i64) -> Self {
+        let percent = |share: f64| (share * 100.0).round() as u32;
+        Self {
+            days,
+            from: report.from.date_naive().to_string(),
+            to: report.to.date_naive().to_string(),
+            peak: report.peak,
+            leases: report.leases,
+            average_lease: report
+                .average_lease
+                .map_or_else(|| "-".into(), format_duration),
+            day_labels: report
+                .days
+                .iter()
+                .map(|day| day.format("%d.%m").to_string())
+                .collect(),
+            groups: report
+                .groups
+                .into_iter()
+                .map(|g| GroupReport {
+                    utilisation: percent(g.utilisation),
+                    daily: g.daily.into_iter().map(percent).collect(),
+                    group: g.group,
+                    hosts: g.hosts,
+                    peak: g.peak,
+                })
+                .collect(),
+            top_users: report
+                .top_users
+                .into_iter()
+                .map(|u| UserReport {
+                    email: u.email,
+                    leases: u.leases,
+                    leased_time: format_duration(u.leased_time),
+                })
+                .collect(),
+            idle_hosts: report.idle_hosts,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct UserInfo {
     pub login: String,
     pub groups: Vec<String>,
+    pub role: Role,
     pub tg_linked: bool,
     pub link: String,
 }
@@ -47,6 +134,7 @@ impl From<User> for UserInfo {
         Self {
             login: value.username,
             groups: value.groups,
+            role: value.role,
             tg_linked: value.tg_handle.is_some(),
             link: value.link,
         }
@@ -57,12 +145,38 @@ impl From<User> for UserInfo {
 pub struct GroupInfo {
     pub id: GroupId,
     pub name: String,
+    pub path: String,
+    pub depth: usize,
 }
-impl From<Group> for GroupInfo {
-    fn from(value: Group) -> Self {
+impl From<GroupNode> for GroupInfo {
+    fn from(value: GroupNode) -> Self {
         Self {
-            id: value.id,
-            name: value.name,
+            id: value.group.id,
+            name: value.group.name,
+            path: value.path,
+            depth: value.depth,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PolicyInfo {
+    pub default_days: i64,
+    pub default_hours: i64,
+    pub max_lease: Option<String>,
+    /// Days input limit, lease can still be a few hours longer than the group maximum
+    pub max_days: i64,
+    pub user_limit: Option<usize>,
+}
+impl From<LeasePolicy> for PolicyInfo {
+    fn from(value: LeasePolicy) -> Self {
+        let default_lease = value.default_lease.unwrap_or(DEFAULT_LEASE_DURATION);
+        Self {
+            default_days: default_lease.num_days(),
+            default_hours: (default_lease - TimeDelta::days(default_lease.num_days())).num_hours(),
+            max_lease: value.max_lease.map(format_duration),
+            max_days: value.max_lease.unwrap_or(MAX_LEASE_DURATION).num_days(),
+            user_limit: value.user_limit.map(|(_, limit)| limit),
         }
     }
 }
@@ -72,14 +186,18 @@ pub struct LeaseInfo {
     pub leased_until: DateTime<Utc>,
     pub valid_for: String,
     pub leased_by: String,
+    pub note: Option<String>,
+    pub ticket: Option<String>,
 }
-impl From<(UserDb, DateTime<Utc>)> for LeaseInfo {
-    fn from(value: (UserDb, DateTime<Utc>)) -> Self {
-        let (user, leased_until) = value;
+impl From<(UserDb, DateTime<Utc>, LeaseNote)> for LeaseInfo {
+    fn from(value: (UserDb, DateTime<Utc>, LeaseNote)) -> Self {
+        let (user, leased_until, note) = value;
         LeaseInfo {
             leased_by: user.email,
             leased_until,
             valid_for: format_duration(leased_until - Utc::now()),
+            note: note.note,
+            ticket: note.ticket,
         }
     }
 }
@@ -100,7 +218,9 @@ impl From<(Host, Option<UserDb>)> for HostInfo {
             hostname: host.hostname,
             ip_address: host.ip_address.ip().to_string(),
             lease_info: match (user, host.leased_until) {
-                (Some(user), Some(leased_until)) => Some((user, leased_until).into()),
+                (Some(user), Some(leased_until)) => {
+                    Some((user, leased_until, host.lease_note).into())
+                }
                 _ => None,
             },
         }
@@ -117,7 +237,7 @@ impl From<LeasedHost> for HostInfo {
             id: value.id,
             hostname: value.hostname,
             ip_address: value.ip_address.ip().to_string(),
-            lease_info: Some((value.user, value.leased_until).into()),
+            lease_info: Some((value.user, value.leased_until, value.note).into()),
         }
     }
 }
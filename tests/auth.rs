@@ -0,0 +1,107 @@
+pub mod support;
+
+use secrecy::SecretString;
+use tachikoma::auth::{AuthProvider, LocalProvider, StaticProvider};
+use tachikoma::configuration::RolesSettings;
+use tachikoma::db::models::Role;
+
+use crate::support::registry::create_registry;
+
+#[tokio::test]
+async fn local_users_are_authenticated_by_password() {
+    let (_, registry) = create_registry().await;
+    let provider = LocalProvider::new(registry);
+    provider
+        .set_user(
+            "alice",
+            &SecretString::from("secret"),
+            "alice@example.org",
+            &["team".into()],
+        )
+        .await
+        .unwrap();
+
+    let info = provider
+        .authenticate("alice", &SecretString::from("secret"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.email, "alice@example.org");
+    assert_eq!(info.groups, vec!["team"]);
+
+    assert!(
+        provider
+            .authenticate("alice", &SecretString::from("wrong"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        provider
+            .authenticate("bob", &SecretString::from("secret"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // updated groups are picked up by authenticated users
+    provider
+        .set_user(
+            "alice",
+            &SecretString::from("new secret"),
+            "alice@example.org",
+            &[],
+        )
+        .await
+        .unwrap();
+    let info = provider.get_user_info(&info.dn).await.unwrap().unwrap();
+    assert!(info.groups.is_empty());
+}
+
+#[tokio::test]
+async fn static_user_is_authenticated_with_any_password() {
+    let provider = StaticProvider::new("dev", "dev@example.org", &["admins".into()]);
+
+    let info = provider
+        .authenticate("dev", &SecretString::from("anything"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.groups, vec!["admins"]);
+    assert!(
+        provider
+            .authenticate("other", &SecretString::from("anything"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(provider.get_user_info(&info.dn).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn roles_are_derived_from_groups() {
+    let roles = RolesSettings {
+        default_role: Role::Viewer,
+        admin_groups: vec!["ops".into()],
+        user_groups: vec!["dev".into()],
+        viewer_groups: vec![],
+    };
+    assert_eq!(roles.role_of(&[]), Role::Viewer);
+    assert_eq!(roles.role_of(&["dev".into()]), Role::User);
+    // the most privileged role wins
+    assert_eq!(roles.role_of(&["dev".into(), "ops".into()]), Role::Admin);
+    assert!(Role::Admin > Role::User && Role::User > Role::Viewer);
+
+    let (mut generator, registry) = create_registry().await;
+    let user = generator.generate_user().await;
+    let mut tx = registry.begin().await.unwrap();
+    tx.set_user_role(&user.id, Role::User).await.unwrap();
+    tx.set_user_role(&user.id, Role::Admin).await.unwrap();
+    tx.commit().await.unwrap();
+    let role: Role = sqlx::query_scalar("SELECT role FROM user_roles WHERE user_id = $1")
+        .bind(user.id.0)
+        .fetch_one(&generator.pool)
+        .await
+        .unwrap();
+    assert_eq!(role, Role::Admin);
+}
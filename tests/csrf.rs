@@ -0,0 +1,89 @@
+use axum::{
+    Extension, Router,
+    body::{Body, to_bytes},
+    http::{Request, StatusCode, header},
+    middleware,
+    routing::get,
+};
+use tachikoma::web::csrf::{CsrfToken, csrf_middleware};
+use tower::ServiceExt;
+use tower_sessions::{MemoryStore, SessionManagerLayer};
+
+fn app() -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(|Extension(CsrfToken(token)): Extension<CsrfToken>| async { token })
+                .post(|| async { "changed" }),
+        )
+        .route_layer(middleware::from_fn(csrf_middleware))
+        .layer(SessionManagerLayer::new(MemoryStore::default()).with_secure(false))
+}
+
+/// Returns session cookie and CSRF token of a new session
+async fn start_session(app: &Router) -> (String, String) {
+    let response = app
+        .clone()
+        .oneshot(Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let cookie = response.headers()[header::SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_owned();
+    let token = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (cookie, String::from_utf8(token.to_vec()).unwrap())
+}
+
+async fn post(app: &Router, cookie: &str, token_header: Option<&str>, form: &str) -> StatusCode {
+    let mut request = Request::post("/")
+        .header(header::COOKIE, cookie)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+    if let Some(token) = token_header {
+        request = request.header("X-CSRF-Token", token);
+    }
+    app.clone()
+        .oneshot(request.body(Body::from(form.to_owned())).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn state_changing_requests_require_token() {
+    let app = app();
+    let (cookie, token) = start_session(&app).await;
+
+    assert_eq!(
+        post(&app, &cookie, None, "hosts_ids=1").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        post(&app, &cookie, Some("wrong"), "hosts_ids=1").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        post(&app, &cookie, Some(&token), "hosts_ids=1").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        post(
+            &app,
+            &cookie,
+            None,
+            &format!("hosts_ids=1&csrf_token={token}")
+        )
+        .await,
+        StatusCode::OK
+    );
+
+    // token of one session is not valid for another one
+    let (other_cookie, _) = start_session(&app).await;
+    assert_eq!(
+        post(&app, &other_cookie, Some(&token), "").await,
+        StatusCode::FORBIDDEN
+    );
+}
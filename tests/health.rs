@@ -0,0 +1,68 @@
+pub mod support;
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use tachikoma::health::{HealthCheck, HealthChecks, Status};
+
+use crate::support::registry::create_registry;
+
+struct Broken;
+
+#[async_trait]
+impl HealthCheck for Broken {
+    async fn check(&self) -> Result<()> {
+        bail!("connection refused")
+    }
+}
+
+#[tokio::test]
+async fn readiness_reports_every_check() {
+    let (_, registry) = create_registry().await;
+    let checks = HealthChecks::default();
+    checks.add("postgres", Arc::new(registry));
+
+    let readiness = checks.readiness().await;
+    assert_eq!(readiness.status, Status::Ok);
+    assert_eq!(readiness.checks["postgres"].status, Status::Ok);
+
+    checks.add("ldap_connect", Arc::new(Broken));
+    let readiness = checks.readiness().await;
+    assert_eq!(readiness.status, Status::Fail);
+    assert_eq!(readiness.checks["postgres"].status, Status::Ok);
+    let json = serde_json::to_value(&readiness).unwrap();
+    assert_eq!(json["status"], "fail");
+    assert_eq!(
+        json["checks"]["ldap_connect"]["error"],
+        "connection refused"
+    );
+    assert!(json["checks"]["postgres"].get("error").is_none());
+}
+
+#[derive(Default)]
+struct Counting(AtomicUsize);
+
+#[async_trait]
+impl HealthCheck for Counting {
+    async fn check(&self) -> Result<()> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn readiness_is_reused_between_probes() {
+    let checks = HealthChecks::default();
+    let counting = Arc::new(Counting::default());
+    checks.add("ldap_connect", counting.clone());
+
+    let (first, second) = tokio::join!(checks.readiness(), checks.readiness());
+    checks.readiness().await;
+    assert_eq!(first.status, Status::Ok);
+    assert_eq!(second.status, Status::Ok);
+    assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+}
@@ -3,8 +3,17 @@ pub mod support;
 use std::collections::HashSet;
 
 use chrono::{TimeDelta, Utc};
+use support::generator::MockPolicy;
 use support::registry::create_service_with_limit;
-use tachikoma::{db::models::HostId, logic::hosts::HostError};
+use tachikoma::{
+    db::models::{GroupId, HostId, LeaseNote},
+    logic::{
+        events::{HostsEvent, HostsEvents},
+        groups::GroupsService,
+        hosts::{HostError, HostsService, MAX_TICKET_LENGTH},
+    },
+};
+use tokio_util::sync::CancellationToken;
 
 use crate::support::registry::{create_registry, create_service};
 
@@ -16,7 +25,13 @@ async fn leasing_host_adds_them_to_user_leased_hosts() {
     let user = generator.generate_user().await;
 
     let leased = hosts_service
-        .lease(&user.id, &vec![], &[leased_host.id], TimeDelta::seconds(42))
+        .lease(
+            &user.id,
+            &vec![],
+            &[leased_host.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -41,7 +56,13 @@ async fn leasing_random_host_leases_one_host() {
     let user = generator.generate_user().await;
 
     let leased = hosts_service
-        .lease_random(&user.id, &vec![], TimeDelta::seconds(42), &group.id)
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::seconds(42)),
+            &group.id,
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -62,13 +83,20 @@ async fn leasing_multiple_hosts() {
             &user.id,
             &vec![],
             &[host1.id, host2.id],
-            TimeDelta::seconds(42),
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
         )
         .await
         .unwrap();
 
     service
-        .lease_random(&user.id, &vec![], TimeDelta::seconds(42), &group.id)
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::seconds(42)),
+            &group.id,
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -94,7 +122,13 @@ async fn freeing_host_makes_it_available_for_lease() {
     assert_eq!(available.len(), 2);
 
     service
-        .lease(&user.id, &vec![], &[host1.id], TimeDelta::seconds(42))
+        .lease(
+            &user.id,
+            &vec![],
+            &[host1.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -126,13 +160,20 @@ async fn free_all_frees_hosts_only_for_one_user() {
             &user1.id,
             &vec![],
             &[host1.id, host2.id],
-            TimeDelta::seconds(42),
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
         )
         .await
         .unwrap();
 
     service
-        .lease(&user2.id, &vec![], &[host3.id], TimeDelta::seconds(42))
+        .lease(
+            &user2.id,
+            &vec![],
+            &[host3.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -150,6 +191,74 @@ async fn free_all_frees_hosts_only_for_one_user() {
     assert_eq!(leased_u2.len(), 1);
 }
 
+#[tokio::test]
+async fn leases_and_releases_are_sent_as_events() {
+    let (mut generator, service) = create_service().await;
+    let host1 = generator.generate_host().await;
+    let host2 = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let mut events = service.events().subscribe();
+
+    service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host1.id, host2.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    service.free(&user.id, &[host1.id]).await.unwrap();
+    service.free_all(&user.id).await.unwrap();
+
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostsEvent::Leased(vec![host1.id, host2.id])
+    );
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostsEvent::Released(vec![host1.id])
+    );
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostsEvent::Released(vec![host2.id])
+    );
+}
+
+#[tokio::test]
+async fn events_are_received_by_every_instance() {
+    let (mut generator, registry) = create_registry().await;
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let shutdown = CancellationToken::new();
+    let first = HostsEvents::listen(&registry, shutdown.clone())
+        .await
+        .unwrap();
+    let second = HostsEvents::listen(&registry, shutdown.clone())
+        .await
+        .unwrap();
+    let mut first_events = first.subscribe();
+    let mut second_events = second.subscribe();
+    let service = HostsService::new(registry, 9999).with_events(first);
+
+    service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host.id],
+            Some(TimeDelta::hours(1)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+
+    let leased = HostsEvent::Leased(vec![host.id]);
+    assert_eq!(first_events.recv().await.unwrap(), leased);
+    assert_eq!(second_events.recv().await.unwrap(), leased);
+    shutdown.cancel();
+}
+
 #[tokio::test]
 async fn leased_until_read() {
     let (mut generator, registry) = create_registry().await;
@@ -197,7 +306,8 @@ async fn lease_limit() {
             &user.id,
             &vec![],
             &[host1.id, host2.id, host3.id],
-            TimeDelta::seconds(42),
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
         )
         .await
     {
@@ -209,7 +319,13 @@ async fn lease_limit() {
     };
 
     service
-        .lease(&user.id, &vec![], &[host1.id], TimeDelta::seconds(42))
+        .lease(
+            &user.id,
+            &vec![],
+            &[host1.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
@@ -219,7 +335,8 @@ async fn lease_limit() {
             &user.id,
             &vec![],
             &[host2.id, host3.id],
-            TimeDelta::seconds(42),
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
         )
         .await
     {
@@ -236,7 +353,8 @@ async fn lease_limit() {
             &user.id,
             &vec![],
             &[host1.id, host2.id],
-            TimeDelta::seconds(42),
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
         )
         .await
     {
@@ -248,13 +366,25 @@ async fn lease_limit() {
     };
 
     service
-        .lease(&user.id, &vec![], &[host2.id], TimeDelta::seconds(42))
+        .lease(
+            &user.id,
+            &vec![],
+            &[host2.id],
+            Some(TimeDelta::seconds(42)),
+            &LeaseNote::default(),
+        )
         .await
         .unwrap();
 
     // leasing random when at limit
     match service
-        .lease_random(&user.id, &vec![], TimeDelta::seconds(42), &group.id)
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::seconds(42)),
+            &group.id,
+            &LeaseNote::default(),
+        )
         .await
     {
         Ok(_) => panic!("Didn't error on lease limit"),
@@ -264,3 +394,431 @@ async fn lease_limit() {
         },
     };
 }
+
+#[tokio::test]
+async fn leasing_random_host_in_parent_group_picks_from_descendants() {
+    let (mut generator, service) = create_service().await;
+    let parent = generator.generate_group().await;
+    let child = generator.generate_subgroup(&parent.id).await;
+    let grandchild = generator.generate_subgroup(&child.id).await;
+    let other = generator.generate_group().await;
+    let host = generator.generate_host_in_group(&grandchild.id).await;
+    generator.generate_host_in_group(&other.id).await;
+    let user = generator.generate_user().await;
+
+    let available = service
+        .get_available_group_hosts(&parent.id, &[])
+        .await
+        .unwrap();
+    assert_eq!(available.len(), 1);
+    assert_eq!(available[0].id, host.id);
+
+    let leased = service
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::seconds(42)),
+            &parent.id,
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(leased.id, host.id);
+
+    match service
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::seconds(42)),
+            &child.id,
+            &LeaseNote::default(),
+        )
+        .await
+    {
+        Err(HostError::ThereIsNoFreeHosts) => (),
+        _ => panic!("Leased host from unrelated group"),
+    }
+}
+
+#[tokio::test]
+async fn leasing_random_host_from_root_picks_from_any_group() {
+    let (mut generator, service) = create_service().await;
+    let group = generator.generate_group().await;
+    let child = generator.generate_subgroup(&group.id).await;
+    let host = generator.generate_host_in_group(&child.id).await;
+    let user = generator.generate_user().await;
+
+    let leased = service
+        .lease_random(
+            &user.id,
+            &vec![],
+            None,
+            &GroupId::ROOT,
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(leased.id, host.id);
+}
+
+#[tokio::test]
+async fn group_policy_limits_lease_duration() {
+    let (mut generator, service) = create_service().await;
+    let group = generator.generate_group().await;
+    let host = generator.generate_host_in_group(&group.id).await;
+    let user = generator.generate_user().await;
+    generator
+        .set_group_policy(
+            &group.id,
+            MockPolicy {
+                max_lease_hours: Some(4),
+                default_lease_hours: Some(3),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    match service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host.id],
+            Some(TimeDelta::hours(5)),
+            &LeaseNote::default(),
+        )
+        .await
+    {
+        Err(HostError::LeaseTooLong { max_hours: 4, .. }) => (),
+        _ => panic!("Wrong error type on too long lease"),
+    }
+
+    let leased = service
+        .lease(&user.id, &vec![], &[host.id], None, &LeaseNote::default())
+        .await
+        .unwrap();
+    let lease_for = leased[0].leased_until - Utc::now();
+    assert!(lease_for > TimeDelta::hours(2) && lease_for <= TimeDelta::hours(3));
+}
+
+#[tokio::test]
+async fn group_policy_allows_leases_longer_than_two_months() {
+    let (mut generator, service) = create_service().await;
+    let group = generator.generate_group().await;
+    let host = generator.generate_host_in_group(&group.id).await;
+    let user = generator.generate_user().await;
+    generator
+        .set_group_policy(
+            &group.id,
+            MockPolicy {
+                max_lease_hours: Some(120 * 24),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host.id],
+            Some(TimeDelta::days(90)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn lease_without_group_policy_is_limited_to_two_months() {
+    let (mut generator, service) = create_service().await;
+    let group = generator.generate_group().await;
+    let host = generator.generate_host_in_group(&group.id).await;
+    let user = generator.generate_user().await;
+
+    let res = service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host.id],
+            Some(TimeDelta::days(90)),
+            &LeaseNote::default(),
+        )
+        .await;
+    assert!(matches!(res, Err(HostError::LeaseTooLong { .. })));
+
+    let res = service
+        .lease_random(
+            &user.id,
+            &vec![],
+            Some(TimeDelta::days(90)),
+            &GroupId::ROOT,
+            &LeaseNote::default(),
+        )
+        .await;
+    assert!(matches!(res, Err(HostError::LeaseTooLong { .. })));
+    assert!(service.get_leased_hosts(&user.id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn group_user_limit_counts_descendant_groups() {
+    let (mut generator, service) = create_service().await;
+    let parent = generator.generate_group().await;
+    let child = generator.generate_subgroup(&parent.id).await;
+    let host1 = generator.generate_host_in_group(&child.id).await;
+    let host2 = generator.generate_host_in_group(&parent.id).await;
+    let other = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    generator
+        .set_group_policy(
+            &parent.id,
+            MockPolicy {
+                user_limit: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    service
+        .lease(&user.id, &vec![], &[host1.id], None, &LeaseNote::default())
+        .await
+        .unwrap();
+    match service
+        .lease(&user.id, &vec![], &[host2.id], None, &LeaseNote::default())
+        .await
+    {
+        Err(HostError::GroupLeaseLimit(_)) => (),
+        _ => panic!("Wrong error type on group lease limit"),
+    }
+    match service
+        .lease_random(&user.id, &vec![], None, &parent.id, &LeaseNote::default())
+        .await
+    {
+        Err(HostError::GroupLeaseLimit(_)) => (),
+        _ => panic!("Wrong error type on group lease limit"),
+    }
+
+    // hosts outside of the group are not affected
+    service
+        .lease(&user.id, &vec![], &[other.id], None, &LeaseNote::default())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn group_access_restricts_ad_groups() {
+    let (mut generator, service) = create_service().await;
+    let parent = generator.generate_group().await;
+    let restricted = generator.generate_subgroup(&parent.id).await;
+    let open = generator.generate_subgroup(&parent.id).await;
+    let restricted_host = generator.generate_host_in_group(&restricted.id).await;
+    let open_host = generator.generate_host_in_group(&open.id).await;
+    let user = generator.generate_user().await;
+    generator.set_group_access(&restricted.id, &["team"]).await;
+
+    match service
+        .lease(
+            &user.id,
+            &vec![],
+            &[restricted_host.id],
+            None,
+            &LeaseNote::default(),
+        )
+        .await
+    {
+        Err(HostError::GroupForbidden(_)) => (),
+        _ => panic!("Wrong error type on forbidden group"),
+    }
+
+    let leased = service
+        .lease_random(&user.id, &vec![], None, &parent.id, &LeaseNote::default())
+        .await
+        .unwrap();
+    assert_eq!(leased.id, open_host.id);
+
+    service
+        .lease(
+            &user.id,
+            &vec!["team".into()],
+            &[restricted_host.id],
+            None,
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn inaccessible_groups_are_hidden() {
+    let (mut generator, registry) = create_registry().await;
+    let hosts_service = HostsService::new(registry.clone(), 9999);
+    let groups_service = GroupsService::new(registry);
+    let parent = generator.generate_group().await;
+    let restricted = generator.generate_subgroup(&parent.id).await;
+    let nested = generator.generate_subgroup(&restricted.id).await;
+    let open = generator.generate_subgroup(&parent.id).await;
+    generator.generate_host_in_group(&nested.id).await;
+    let open_host = generator.generate_host_in_group(&open.id).await;
+    generator.set_group_access(&restricted.id, &["team"]).await;
+
+    let available = hosts_service
+        .get_available_group_hosts(&parent.id, &["other".into()])
+        .await
+        .unwrap();
+    assert_eq!(
+        available.iter().map(|h| h.id).collect::<Vec<_>>(),
+        vec![open_host.id]
+    );
+    let groups: HashSet<_> = groups_service
+        .get_accessible_groups_tree(&["other".into()])
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|node| node.group.id)
+        .collect();
+    assert!(groups.contains(&open.id));
+    assert!(!groups.contains(&restricted.id));
+    assert!(!groups.contains(&nested.id));
+
+    // nested group with its own ACL keeps the restricted parent visible
+    let other_only = generator.generate_subgroup(&restricted.id).await;
+    generator.set_group_access(&other_only.id, &["other"]).await;
+    let groups: HashSet<_> = groups_service
+        .get_accessible_groups_tree(&["other".into()])
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|node| node.group.id)
+        .collect();
+    assert!(groups.contains(&other_only.id));
+    assert!(groups.contains(&restricted.id));
+    assert!(!groups.contains(&nested.id));
+
+    let available = hosts_service
+        .get_available_group_hosts(&parent.id, &["team".into()])
+        .await
+        .unwrap();
+    assert_eq!(available.len(), 2);
+}
+
+#[tokio::test]
+async fn lease_note_is_editable_and_kept_in_history() {
+    let (mut generator, registry) = create_registry().await;
+    let events = HostsEvents::listen(&registry, CancellationToken::new())
+        .await
+        .unwrap();
+    let service = HostsService::new(registry.clone(), 9999).with_events(events);
+    let mut events = service.events().subscribe();
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let note = LeaseNote {
+        note: Some("Testing the new release".to_string()),
+        ticket: Some("https://tracker.example.com/PROJ-1".to_string()),
+    };
+
+    let leased = service
+        .lease(
+            &user.id,
+            &vec![],
+            &[host.id],
+            Some(TimeDelta::hours(1)),
+            &note,
+        )
+        .await
+        .unwrap();
+    assert_eq!(leased[0].note, note);
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostsEvent::Leased(vec![host.id])
+    );
+
+    let updated = LeaseNote {
+        note: Some("Reproducing PROJ-2".to_string()),
+        ticket: None,
+    };
+    service
+        .set_note(&user.id, &[host.id], &updated)
+        .await
+        .unwrap();
+    assert_eq!(
+        events.recv().await.unwrap(),
+        HostsEvent::Updated(vec![host.id])
+    );
+    let leased = service.get_leased_hosts(&user.id).await.unwrap();
+    assert_eq!(leased[0].note, updated);
+
+    service.free(&user.id, &[host.id]).await.unwrap();
+    let mut tx = registry.begin().await.unwrap();
+    let history = tx
+        .get_lease_history(Utc::now() - TimeDelta::hours(1), Utc::now())
+        .await
+        .unwrap();
+    let free = tx.get_inventory_hosts().await.unwrap();
+    tx.commit().await.unwrap();
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].note, updated);
+    assert!(free[0].lease_note.is_empty());
+}
+
+#[tokio::test]
+async fn lease_note_of_other_users_hosts_is_not_changed() {
+    let (mut generator, service) = create_service().await;
+    let host = generator.generate_host().await;
+    let owner = generator.generate_user().await;
+    let other = generator.generate_user().await;
+    let note = LeaseNote {
+        note: Some("Mine".to_string()),
+        ticket: None,
+    };
+    service
+        .lease(&owner.id, &vec![], &[host.id], None, &note)
+        .await
+        .unwrap();
+
+    service
+        .set_note(&other.id, &[host.id], &LeaseNote::default())
+        .await
+        .unwrap();
+
+    let leased = service.get_leased_hosts(&owner.id).await.unwrap();
+    assert_eq!(leased[0].note, note);
+}
+
+#[tokio::test]
+async fn invalid_lease_ticket_is_rejected() {
+    let (mut generator, service) = create_service().await;
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let note = LeaseNote {
+        note: None,
+        ticket: Some("javascript:alert(1)".to_string()),
+    };
+
+    let res = service
+        .lease(&user.id, &vec![], &[host.id], None, &note)
+        .await;
+
+    assert!(matches!(res, Err(HostError::InvalidNote(_))));
+    assert!(service.get_leased_hosts(&user.id).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn too_long_lease_ticket_is_rejected() {
+    let (mut generator, service) = create_service().await;
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let note = LeaseNote {
+        note: None,
+        ticket: Some(format!(
+            "https://tracker.example.com/{}",
+            "a".repeat(MAX_TICKET_LENGTH)
+        )),
+    };
+
+    let res = service
+        .lease(&user.id, &vec![], &[host.id], None, &note)
+        .await;
+
+    assert!(matches!(res, Err(HostError::InvalidNote(_))));
+    assert!(service.get_leased_hosts(&user.id).await.unwrap().is_empty());
+}
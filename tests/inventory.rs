@@ -0,0 +1,288 @@
+pub mod support;
+
+use std::collections::HashSet;
+
+use chrono::TimeDelta;
+use tachikoma::db::models::{GroupId, LeaseNote};
+use tachikoma::logic::hosts::HostError;
+use tachikoma::logic::inventory::{InventoryError, InventoryFormat, LineError};
+
+use crate::support::registry::create_inventory_service;
+
+const CSV: &str = "hostname,ip_address,group,tags
+perf-1,10.0.0.1,perf,arm;fast
+perf-2,10.0.0.2,perf,
+root-1,10.0.0.3,root,x86
+";
+
+#[test]
+fn csv_is_parsed() {
+    let records = InventoryFormat::Csv.parse(CSV).unwrap();
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].hostname, "perf-1");
+    assert_eq!(records[0].ip_address.to_string(), "10.0.0.1");
+    assert_eq!(records[0].group, "perf");
+    assert_eq!(records[0].tags, vec!["arm", "fast"]);
+    assert!(records[1].tags.is_empty());
+}
+
+#[test]
+fn errors_are_reported_for_every_line() {
+    let csv = "hostname,ip_address,group,tags
+ok,10.0.0.1,perf,
+bad-ip,10.0.0.300,perf,
+,10.0.0.2,perf,
+ok,10.0.0.3,perf,
+";
+    let Err(InventoryError::Invalid(errors)) = InventoryFormat::Csv.parse(csv) else {
+        panic!("Invalid inventory was parsed")
+    };
+
+    assert_eq!(
+        errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+        vec![3, 4, 5]
+    );
+    assert_eq!(
+        errors[2],
+        LineError {
+            line: 5,
+            message: "duplicate hostname 'ok', first defined on line 2".into()
+        }
+    );
+}
+
+#[test]
+fn yaml_errors_point_to_entry_line() {
+    let yaml = "- hostname: perf-1
+  ip_address: 10.0.0.1
+  group: perf
+- hostname: perf-2
+  ip_address: not-an-ip
+  group: perf
+  tags: [arm]
+";
+    let Err(InventoryError::Invalid(errors)) = InventoryFormat::Yaml.parse(yaml) else {
+        panic!("Invalid inventory was parsed")
+    };
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 4);
+}
+
+#[tokio::test]
+async fn dry_run_does_not_change_inventory() {
+    let (_, service, _) = create_inventory_service().await;
+    let records = InventoryFormat::Csv.parse(CSV).unwrap();
+
+    let diff = service.import(&records, true).await.unwrap();
+
+    assert_eq!(diff.new_groups, vec!["perf"]);
+    assert_eq!(diff.created.len(), 3);
+    assert!(service.export().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn import_creates_and_updates_hosts() {
+    let (mut generator, service, hosts_service) = create_inventory_service().await;
+    let existing = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    hosts_service
+        .lease(
+            &user.id,
+            &vec![],
+            &[existing.id],
+            Some(TimeDelta::hours(1)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+
+    let csv = format!("{CSV}{},10.0.1.1,perf,moved\n", existing.hostname);
+    let records = InventoryFormat::Csv.parse(&csv).unwrap();
+
+    let diff = service.import(&records, false).await.unwrap();
+    assert_eq!(diff.created.len(), 3);
+    assert_eq!(diff.updated.len(), 1);
+    assert_eq!(diff.updated[0].host_id, existing.id);
+
+    let exported = service.export().await.unwrap();
+    assert_eq!(exported.len(), 4);
+    let moved = exported
+        .iter()
+        .find(|r| r.hostname == existing.hostname)
+        .unwrap();
+    assert_eq!(moved.group, "perf");
+    assert_eq!(moved.tags, vec!["moved"]);
+
+    // lease survives the update
+    let leased = hosts_service.get_leased_hosts(&user.id).await.unwrap();
+    assert_eq!(leased.len(), 1);
+    assert_eq!(leased[0].id, existing.id);
+
+    let diff = service.import(&records, false).await.unwrap();
+    assert!(diff.is_empty());
+    assert_eq!(diff.unchanged, 4);
+}
+
+#[tokio::test]
+async fn export_can_be_imported_back() {
+    let (_, service, _) = create_inventory_service().await;
+    service
+        .import(&InventoryFormat::Csv.parse(CSV).unwrap(), false)
+        .await
+        .unwrap();
+
+    for format in [InventoryFormat::Csv, InventoryFormat::Yaml] {
+        let rendered = format.render(&service.export().await.unwrap()).unwrap();
+        let diff = service
+            .import(&format.parse(&rendered).unwrap(), true)
+            .await
+            .unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, 3);
+    }
+}
+
+#[test]
+fn toml_is_parsed() {
+    let toml = r#"
+[[hosts]]
+hostname = "perf-1"
+ip_address = "10.0.0.1"
+group = "perf"
+tags = ["arm"]
+
+[[hosts]]
+hostname = "perf-2"
+ip_address = "10.0.0.2"
+"#;
+    let Err(InventoryError::Invalid(errors)) = InventoryFormat::Toml.parse(toml) else {
+        panic!("Invalid inventory was parsed")
+    };
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 8);
+
+    let records = InventoryFormat::Toml
+        .parse(&format!("{toml}group = \"perf\"\n"))
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].tags, vec!["arm"]);
+}
+
+#[tokio::test]
+async fn sync_retires_missing_hosts_and_keeps_leases() {
+    let (mut generator, service, hosts_service) = create_inventory_service().await;
+    let free = generator.generate_host().await;
+    let leased = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    hosts_service
+        .lease(
+            &user.id,
+            &vec![],
+            &[leased.id],
+            Some(TimeDelta::hours(1)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+
+    let records = InventoryFormat::Csv.parse(CSV).unwrap();
+    let diff = service.sync(&records, false).await.unwrap();
+    assert_eq!(diff.created.len(), 3);
+    assert_eq!(
+        diff.retired
+            .iter()
+            .map(|h| h.host_id)
+            .collect::<HashSet<_>>(),
+        HashSet::from([free.id, leased.id])
+    );
+
+    let still_leased = hosts_service.get_leased_hosts(&user.id).await.unwrap();
+    assert_eq!(still_leased.len(), 1);
+    assert_eq!(still_leased[0].id, leased.id);
+
+    let available = hosts_service.get_available_hosts().await.unwrap();
+    assert_eq!(available.len(), 3);
+    assert!(!available.iter().any(|h| h.id == free.id));
+    match hosts_service
+        .lease(
+            &user.id,
+            &vec![],
+            &[free.id],
+            Some(TimeDelta::hours(1)),
+            &LeaseNote::default(),
+        )
+        .await
+    {
+        Err(HostError::Retired(ids)) => assert_eq!(ids, vec![free.id]),
+        _ => panic!("Retired host was leased"),
+    }
+
+    // bringing host back into the file restores it
+    let csv = format!("{CSV}{},10.0.1.1,root,\n", free.hostname);
+    let diff = service
+        .sync(&InventoryFormat::Csv.parse(&csv).unwrap(), false)
+        .await
+        .unwrap();
+    assert_eq!(diff.updated.len(), 1);
+    assert!(diff.updated[0].restore);
+    assert!(diff.retired.is_empty());
+    assert_eq!(hosts_service.get_available_hosts().await.unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn nested_groups_are_created_from_paths() {
+    let (mut generator, service, hosts_service) = create_inventory_service().await;
+    let csv = "hostname,ip_address,group,tags
+arm-1,10.0.0.1,perf/arm,
+x86-1,10.0.0.2, perf / x86 ,
+";
+    let diff = service
+        .import(&InventoryFormat::Csv.parse(csv).unwrap(), false)
+        .await
+        .unwrap();
+    assert_eq!(diff.new_groups, vec!["perf", "perf/arm", "perf/x86"]);
+
+    let mut groups: Vec<_> = service
+        .export()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.group)
+        .collect();
+    groups.sort();
+    assert_eq!(groups, vec!["perf/arm", "perf/x86"]);
+
+    // new top level groups are put under root
+    let user = generator.generate_user().await;
+    hosts_service
+        .lease_random(
+            &user.id,
+            &vec![],
+            None,
+            &GroupId::ROOT,
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn root_prefix_of_group_path_is_dropped() {
+    let (_, service, _) = create_inventory_service().await;
+    let csv = "hostname,ip_address,group,tags
+perf-1,10.0.0.1,perf,
+perf-2,10.0.0.2,root/perf,
+arm-1,10.0.0.3,root / perf/arm,
+root-1,10.0.0.4,root,
+";
+    let records = InventoryFormat::Csv.parse(csv).unwrap();
+    assert_eq!(
+        records.iter().map(|r| r.group.as_str()).collect::<Vec<_>>(),
+        vec!["perf", "perf", "perf/arm", "root"]
+    );
+
+    let diff = service.import(&records, false).await.unwrap();
+    assert_eq!(diff.new_groups, vec!["perf", "perf/arm"]);
+}
@@ -0,0 +1,266 @@
+pub mod support;
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::anyhow;
+use ldap3::SearchEntry;
+use tachikoma::ldap::{
+    AdUserInfo, MAX_GROUP_NESTING,
+    cache::{CacheStats, UserInfoCache},
+    pool::LdapPool,
+    schema::{GroupEntry, GroupName, GroupSearch, LdapSchema},
+    with_parent_groups,
+};
+
+use crate::support::setup_settings;
+
+fn user_info(groups: &[&str]) -> AdUserInfo {
+    AdUserInfo {
+        dn: "CN=user,DC=example,DC=org".into(),
+        email: "user@example.org".into(),
+        groups: groups.iter().map(|g| g.to_string()).collect(),
+    }
+}
+
+#[tokio::test]
+async fn user_info_is_cached_until_ttl() {
+    let cache = UserInfoCache::new(Duration::from_secs(60), Duration::ZERO);
+    let dn = user_info(&[]).dn;
+
+    let info = cache
+        .get_or_fetch(&dn, async { Ok(Some(user_info(&["team"]))) })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.groups, vec!["team"]);
+
+    let info = cache
+        .get_or_fetch(&dn, async { panic!("Cached user info was fetched") })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.groups, vec!["team"]);
+
+    assert_eq!(
+        cache.stats(),
+        CacheStats {
+            hits: 1,
+            misses: 1,
+            stale_hits: 0
+        }
+    );
+}
+
+#[tokio::test]
+async fn stale_user_info_is_used_on_errors() {
+    let cache = UserInfoCache::new(Duration::ZERO, Duration::from_secs(60));
+    let dn = user_info(&[]).dn;
+    cache.insert(user_info(&["team"]));
+
+    let info = cache
+        .get_or_fetch(&dn, async { Err(anyhow!("AD is down")) })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.groups, vec!["team"]);
+
+    // expired entries are refreshed when AD is available
+    let info = cache
+        .get_or_fetch(&dn, async { Ok(Some(user_info(&["other"]))) })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.groups, vec!["other"]);
+
+    let no_stale = UserInfoCache::new(Duration::ZERO, Duration::ZERO);
+    no_stale.insert(user_info(&["team"]));
+    assert!(
+        no_stale
+            .get_or_fetch(&dn, async { Err(anyhow!("AD is down")) })
+            .await
+            .is_err()
+    );
+    assert_eq!(cache.stats().stale_hits, 1);
+}
+
+#[tokio::test]
+async fn unavailable_ldap_is_reported_as_error() {
+    let mut settings = setup_settings().ldap.unwrap();
+    settings.url = "ldap://127.0.0.1:1".into();
+    // failed connections give their permit back
+    settings.max_connections = 1;
+    let pool = LdapPool::new(settings);
+
+    assert!(pool.get().await.is_err());
+    assert!(pool.connect().await.is_err());
+}
+
+#[test]
+fn ad_entries_are_parsed_by_default() {
+    let schema = LdapSchema::default();
+    assert_eq!(
+        schema.login_filter("alice"),
+        "(|(mail=alice)(sAMAccountName=alice))"
+    );
+
+    let entry = SearchEntry {
+        dn: "CN=alice,OU=users,DC=example,DC=org".into(),
+        attrs: HashMap::from([
+            ("mail".into(), vec!["alice@example.org".into()]),
+            (
+                "memberOf".into(),
+                vec![
+                    "CN=team,OU=groups,DC=example,DC=org".into(),
+                    "CN=admins,OU=groups,DC=example,DC=org".into(),
+                ],
+            ),
+        ]),
+        bin_attrs: HashMap::new(),
+    };
+    let info = schema.user_info(entry, None).unwrap();
+    assert_eq!(info.email, "alice@example.org");
+    assert_eq!(info.groups, vec!["team", "admins"]);
+}
+
+#[test]
+fn ldap_schema_is_configurable() {
+    let schema = LdapSchema {
+        login_filter: "(uid={login})".into(),
+        email_attribute: "email".into(),
+        group_attribute: "memberOf".into(),
+        group_name: GroupName::Value,
+        ..Default::default()
+    };
+    assert_eq!(schema.login_filter("alice"), "(uid=alice)");
+
+    let entry = SearchEntry {
+        dn: "uid=alice,cn=users,dc=example,dc=org".into(),
+        attrs: HashMap::from([
+            ("email".into(), vec!["alice@example.org".into()]),
+            (
+                "memberOf".into(),
+                vec!["cn=team,cn=groups,dc=example,dc=org".into()],
+            ),
+        ]),
+        bin_attrs: HashMap::new(),
+    };
+    let info = schema.user_info(entry.clone(), None).unwrap();
+    assert_eq!(info.groups, vec!["cn=team,cn=groups,dc=example,dc=org"]);
+
+    // lowercase DNs of FreeIPA
+    let schema = LdapSchema {
+        group_name: GroupName::Cn,
+        ..schema
+    };
+    assert_eq!(
+        schema.user_info(entry.clone(), None).unwrap().groups,
+        vec!["team"]
+    );
+    // groups found with a separate search
+    assert_eq!(
+        schema
+            .user_info(entry, Some(vec!["other".into()]))
+            .unwrap()
+            .groups,
+        vec!["other"]
+    );
+}
+
+#[test]
+fn filter_input_is_escaped() {
+    let schema = LdapSchema::default();
+    assert_eq!(
+        schema.login_filter("*)(mail=*"),
+        r"(|(mail=\2a\29\28mail=\2a)(sAMAccountName=\2a\29\28mail=\2a))"
+    );
+
+    let search = GroupSearch {
+        base: "ou=groups,dc=example,dc=org".into(),
+        filter: "(member={dn})".into(),
+        name_attribute: "cn".into(),
+    };
+    assert_eq!(
+        search.filter(r"cn=Doe\, John (admin),dc=example,dc=org"),
+        r"(member=cn=Doe\5c, John \28admin\29,dc=example,dc=org)"
+    );
+}
+
+#[test]
+fn nested_groups_are_searched_in_the_whole_domain() {
+    let schema = LdapSchema::default();
+    assert_eq!(
+        schema
+            .in_chain_base("OU=users,OU=corp,DC=example,DC=org")
+            .unwrap(),
+        "DC=example,DC=org"
+    );
+    assert!(schema.in_chain_base("OU=users,O=example").is_err());
+    assert_eq!(
+        schema.in_chain_filter(r"CN=Doe\, John,DC=example,DC=org"),
+        r"(member:1.2.840.113556.1.4.1941:=CN=Doe\5c, John,DC=example,DC=org)"
+    );
+
+    let schema = LdapSchema {
+        group_search: Some(GroupSearch {
+            base: "OU=groups,DC=example,DC=org".into(),
+            filter: "(member={dn})".into(),
+            name_attribute: "cn".into(),
+        }),
+        ..Default::default()
+    };
+    assert_eq!(
+        schema.in_chain_base("OU=users,DC=example,DC=org").unwrap(),
+        "OU=groups,DC=example,DC=org"
+    );
+}
+
+fn group(dn: &str) -> GroupEntry {
+    GroupEntry {
+        dn: dn.into(),
+        name: Some(dn.into()),
+    }
+}
+
+/// Resolves parents of `groups` with `parents` as the directory, returns names and number of lookups
+async fn resolve(groups: &[&str], parents: &HashMap<String, Vec<String>>) -> (Vec<String>, usize) {
+    let lookups = std::cell::Cell::new(0);
+    let resolved = with_parent_groups(groups.iter().map(|dn| group(dn)).collect(), |dn| {
+        lookups.set(lookups.get() + 1);
+        let found = parents
+            .get(&dn)
+            .map(|p| p.iter().map(|dn| group(dn)).collect())
+            .unwrap_or_default();
+        async move { Ok(found) }
+    })
+    .await
+    .unwrap();
+    (
+        resolved.into_iter().filter_map(|g| g.name).collect(),
+        lookups.get(),
+    )
+}
+
+#[tokio::test]
+async fn nested_groups_are_resolved_recursively() {
+    let parents = HashMap::from([
+        (
+            "team".to_string(),
+            vec!["dev".to_string(), "ops".to_string()],
+        ),
+        ("dev".to_string(), vec!["staff".to_string()]),
+        ("ops".to_string(), vec!["staff".to_string()]),
+        // cycles are only looked up once
+        ("staff".to_string(), vec!["team".to_string()]),
+    ]);
+    let (groups, lookups) = resolve(&["team"], &parents).await;
+    assert_eq!(groups, vec!["team", "dev", "ops", "staff"]);
+    assert_eq!(lookups, 4);
+
+    let chain: HashMap<_, _> = (0..MAX_GROUP_NESTING + 5)
+        .map(|level| (format!("g{level}"), vec![format!("g{}", level + 1)]))
+        .collect();
+    let (groups, lookups) = resolve(&["g0"], &chain).await;
+    assert_eq!(groups.len(), MAX_GROUP_NESTING + 1);
+    assert_eq!(groups.last().unwrap(), &format!("g{MAX_GROUP_NESTING}"));
+    assert_eq!(lookups, MAX_GROUP_NESTING);
+}
@@ -0,0 +1,101 @@
+pub mod support;
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use tachikoma::logic::leader::{LEADER_LOCK, LeaderElection};
+use tokio::{task::JoinHandle, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::support::registry::create_registry;
+
+struct Instance {
+    running: Arc<AtomicBool>,
+    shutdown: CancellationToken,
+    handle: JoinHandle<()>,
+}
+
+impl Instance {
+    fn start(election: LeaderElection) -> Self {
+        let running = Arc::new(AtomicBool::new(false));
+        let shutdown = CancellationToken::new();
+        let job_running = running.clone();
+        let token = shutdown.clone();
+        let handle = tokio::spawn(async move {
+            election
+                .run(token, |leading| {
+                    let running = job_running.clone();
+                    async move {
+                        assert!(!running.swap(true, Ordering::SeqCst));
+                        leading.cancelled().await;
+                        running.store(false, Ordering::SeqCst);
+                    }
+                })
+                .await
+        });
+        Self {
+            running,
+            shutdown,
+            handle,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+async fn wait_for(condition: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Condition wasn't met in 5 seconds");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_leader_runs_jobs_and_another_instance_takes_over() {
+    let (generator, registry) = create_registry().await;
+    let election = LeaderElection::new(registry, Duration::from_millis(100));
+    let first = Instance::start(election.clone());
+    let second = Instance::start(election);
+
+    wait_for(|| first.is_running() || second.is_running()).await;
+    sleep(Duration::from_millis(300)).await;
+    assert!(first.is_running() != second.is_running());
+    let (leader, follower) = if first.is_running() {
+        (first, second)
+    } else {
+        (second, first)
+    };
+
+    // connection of the leader is lost
+    sqlx::query(
+        "SELECT pg_terminate_backend(pid) FROM pg_locks
+        WHERE locktype = 'advisory' AND granted
+            AND database = (SELECT oid FROM pg_database WHERE datname = current_database())
+            AND (classid::bigint << 32 | objid::bigint) = $1",
+    )
+    .bind(LEADER_LOCK)
+    .execute(&generator.pool)
+    .await
+    .unwrap();
+    wait_for(|| follower.is_running()).await;
+    assert!(!leader.is_running());
+
+    // the old leader becomes a follower, after the new one shuts down it takes over again
+    follower.shutdown.cancel();
+    follower.handle.await.unwrap();
+    wait_for(|| leader.is_running()).await;
+
+    leader.shutdown.cancel();
+    leader.handle.await.unwrap();
+    assert!(!leader.running.load(Ordering::SeqCst));
+}
@@ -0,0 +1,66 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::Value;
+use tachikoma::telemetry::json_layer;
+use tracing_subscriber::{fmt::MakeWriter, prelude::*};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MakeWriter<'_> for Buffer {
+    type Writer = Buffer;
+
+    fn make_writer(&self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn json_lines_include_span_fields() {
+    let buffer = Buffer::default();
+    let subscriber = tracing_subscriber::registry().with(json_layer().with_writer(buffer.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let request = tracing::info_span!(
+            "http-request",
+            request_id = "f3b1",
+            username = tracing::field::Empty,
+            user_id = tracing::field::Empty,
+        );
+        let _request = request.enter();
+        request.record("username", "user@example.org");
+        request.record("user_id", 7);
+
+        let lease = tracing::info_span!("lease", hosts = 2);
+        let _lease = lease.enter();
+        tracing::info!(host = "host-1", "Leased");
+    });
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 1);
+    let line: Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(line["target"], "logging");
+    assert_eq!(line["message"], "Leased");
+    assert_eq!(line["host"], "host-1");
+    assert_eq!(line["hosts"], 2);
+    assert_eq!(line["request_id"], "f3b1");
+    assert_eq!(line["username"], "user@example.org");
+    assert_eq!(line["user_id"], 7);
+    assert_eq!(line["spans"], "http-request:lease");
+    assert!(line["timestamp"].is_string());
+}
@@ -0,0 +1,108 @@
+pub mod support;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::http::HeaderMap;
+use chrono::{TimeDelta, Utc};
+use tachikoma::{
+    configuration::LoginThrottleSettings,
+    logic::throttling::{LoginThrottle, ThrottleError},
+};
+
+use crate::support::registry::create_registry;
+
+const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+fn settings() -> LoginThrottleSettings {
+    LoginThrottleSettings {
+        max_failures: 2,
+        lockout_secs: 30,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn login_is_locked_after_failures() {
+    let (_, registry) = create_registry().await;
+    let throttle = LoginThrottle::new(registry, settings());
+
+    assert!(
+        throttle
+            .record_failure("alice", Some(IP))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    throttle.check("alice", Some(IP)).await.unwrap();
+
+    let until = throttle
+        .record_failure("Alice", Some(IP))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(until > Utc::now() + TimeDelta::seconds(25));
+    for (username, ip) in [("alice", None), ("bob", Some(IP))] {
+        match throttle.check(username, ip).await {
+            // database keeps microseconds only
+            Err(ThrottleError::Locked(locked_until)) => {
+                assert!((locked_until - until).abs() < TimeDelta::milliseconds(1))
+            }
+            _ => panic!("Locked login was allowed"),
+        }
+    }
+    throttle.check("bob", None).await.unwrap();
+
+    // every next failure doubles lockout
+    let next = throttle
+        .record_failure("alice", None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(next > Utc::now() + TimeDelta::seconds(55));
+}
+
+#[tokio::test]
+async fn successful_login_resets_username_failures() {
+    let (_, registry) = create_registry().await;
+    let throttle = LoginThrottle::new(registry, settings());
+
+    throttle.record_failure("alice", Some(IP)).await.unwrap();
+    throttle.record_success("alice").await.unwrap();
+    assert!(
+        throttle
+            .record_failure("alice", None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // failures of the address are kept
+    assert!(
+        throttle
+            .record_failure("bob", Some(IP))
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn forwarded_address_is_used_only_when_trusted() {
+    let (_, registry) = create_registry().await;
+    let peer: SocketAddr = "127.0.0.1:4242".parse().unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", "1.1.1.1, 10.0.0.1".parse().unwrap());
+
+    let throttle = LoginThrottle::new(registry.clone(), settings());
+    assert_eq!(throttle.client_ip(peer, &headers), peer.ip());
+
+    let throttle = LoginThrottle::new(
+        registry,
+        LoginThrottleSettings {
+            trust_forwarded_for: true,
+            ..settings()
+        },
+    );
+    assert_eq!(throttle.client_ip(peer, &headers), IP);
+    assert_eq!(throttle.client_ip(peer, &HeaderMap::new()), peer.ip());
+}
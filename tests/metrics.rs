@@ -0,0 +1,100 @@
+pub mod support;
+
+use std::sync::Arc;
+
+use chrono::TimeDelta;
+use tachikoma::{
+    auth::StaticProvider,
+    configuration::MetricsSettings,
+    db::{Registry, models::LeaseNote},
+    metrics::METRICS,
+    web::Application,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::support::{configure_db, registry::create_service, setup_settings};
+
+#[tokio::test]
+async fn hosts_and_leases_are_exported() {
+    let (mut generator, service) = create_service().await;
+    let group = generator.generate_group().await;
+    let leased = generator.generate_host_in_group(&group.id).await;
+    generator.generate_host_in_group(&group.id).await;
+    let user = generator.generate_user().await;
+
+    service
+        .lease(
+            &user.id,
+            &vec![],
+            &[leased.id],
+            Some(TimeDelta::hours(3)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    service.update_metrics().await.unwrap();
+
+    let hosts = |state| {
+        METRICS
+            .hosts
+            .with_label_values(&[group.name.as_str(), state])
+            .get()
+    };
+    assert_eq!(hosts("leased"), 1);
+    assert_eq!(hosts("free"), 1);
+    assert_eq!(hosts("retired"), 0);
+    assert_eq!(METRICS.lease_duration.get_sample_count(), 1);
+    assert_eq!(METRICS.lease_duration.get_sample_sum(), 3.0);
+
+    let rendered = METRICS.render();
+    assert!(rendered.contains(&format!(
+        "tachikoma_hosts{{group=\"{}\",state=\"leased\"}} 1",
+        group.name
+    )));
+    assert!(rendered.contains(&format!(
+        "tachikoma_leasing_users{{group=\"{}\"}} 1",
+        group.name
+    )));
+    assert!(!rendered.contains(&user.email));
+}
+
+#[tokio::test]
+async fn metrics_are_served_on_separate_address() {
+    let mut settings = setup_settings();
+    configure_db(&settings.database).await;
+    settings.metrics = Some(MetricsSettings {
+        host: "127.0.0.1".parse().unwrap(),
+        port: 0,
+    });
+    let registry = Registry::new(&settings.database).await.unwrap();
+    let provider = Arc::new(StaticProvider::new("dev", "dev@example.org", &[]));
+    let app = Application::build(&settings, registry, provider, "".into())
+        .await
+        .unwrap();
+    let app_addr = app.listening_addr();
+    let metrics_addr = app.metrics_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let server = tokio::spawn(app.serve_until(shutdown.clone()));
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("http://{metrics_addr}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await.unwrap().contains("tachikoma_"));
+
+    let response = client
+        .get(format!("http://{app_addr}/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(response.status(), 200);
+
+    shutdown.cancel();
+    server.await.unwrap().unwrap();
+}
@@ -0,0 +1,199 @@
+pub mod support;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::Utc;
+use secrecy::SecretString;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tachikoma::{
+    auth::{AuthProvider, OidcClient},
+    configuration::OidcSettings,
+};
+use tokio::net::TcpListener;
+
+use crate::support::registry::create_registry;
+
+#[derive(Clone, Default)]
+struct MockIdp {
+    issuer: String,
+    /// Parameters of the last authorization request, like IdP would remember them for the code
+    authorization: Arc<Mutex<HashMap<String, String>>>,
+    claims: Arc<Mutex<Value>>,
+}
+
+async fn discovery(State(idp): State<MockIdp>) -> Json<Value> {
+    Json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+    }))
+}
+
+async fn token(
+    State(idp): State<MockIdp>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let authorization = idp.authorization.lock().unwrap().clone();
+    let challenge = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+    if form["code"] != "code" || challenge != authorization["code_challenge"] {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut claims = idp.claims.lock().unwrap().clone();
+    if claims.get("nonce").is_none() {
+        claims["nonce"] = json!(authorization["nonce"]);
+    }
+    let encode = |value: Value| BASE64_URL_SAFE_NO_PAD.encode(value.to_string());
+    let id_token = format!("{}.{}.", encode(json!({"alg": "none"})), encode(claims));
+    Ok(Json(
+        json!({ "id_token": id_token, "access_token": "token" }),
+    ))
+}
+
+async fn start_idp() -> MockIdp {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let idp = MockIdp {
+        issuer: format!("http://{}", listener.local_addr().unwrap()),
+        ..Default::default()
+    };
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/token", post(token))
+        .with_state(idp.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    idp
+}
+
+fn settings(idp: &MockIdp) -> OidcSettings {
+    OidcSettings {
+        issuer_url: idp.issuer.clone(),
+        client_id: "tachikoma".into(),
+        client_secret: SecretString::from("secret"),
+        redirect_url: "http://localhost/login/oidc/callback".into(),
+        groups_claim: "roles".into(),
+        scopes: vec!["openid".into(), "email".into()],
+    }
+}
+
+/// Follows redirect to the IdP, which is skipped in tests
+async fn authorize(idp: &MockIdp, client: &OidcClient) -> tachikoma::auth::OidcFlow {
+    let (url, flow) = client.start().await.unwrap();
+    let url = reqwest::Url::parse(&url).unwrap();
+    assert!(
+        url.as_str()
+            .starts_with(&format!("{}/authorize", idp.issuer))
+    );
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    assert_eq!(params["state"], flow.state);
+    assert_eq!(params["client_id"], "tachikoma");
+    *idp.authorization.lock().unwrap() = params;
+    flow
+}
+
+#[tokio::test]
+async fn oidc_claims_are_mapped_to_user_info() {
+    let (_, registry) = create_registry().await;
+    let idp = start_idp().await;
+    let client = OidcClient::new(settings(&idp), registry);
+    *idp.claims.lock().unwrap() = json!({
+        "iss": idp.issuer,
+        "aud": "tachikoma",
+        "exp": Utc::now().timestamp() + 60,
+        "sub": "42",
+        "email": "alice@example.org",
+        "email_verified": true,
+        "roles": ["team", "admins"],
+    });
+
+    let flow = authorize(&idp, &client).await;
+    let info = client.finish(&flow, "code").await.unwrap();
+    assert_eq!(info.email, "alice@example.org");
+    assert_eq!(info.groups, vec!["team", "admins"]);
+
+    // claims are kept for requests after login
+    let saved = client.get_user_info(&info.dn).await.unwrap().unwrap();
+    assert_eq!(saved.groups, info.groups);
+
+    assert!(client.finish(&flow, "wrong code").await.is_err());
+}
+
+#[tokio::test]
+async fn invalid_id_tokens_are_rejected() {
+    let (_, registry) = create_registry().await;
+    let idp = start_idp().await;
+    let client = OidcClient::new(settings(&idp), registry);
+    let valid = json!({
+        "iss": idp.issuer,
+        "aud": ["tachikoma"],
+        "exp": Utc::now().timestamp() + 60,
+        "sub": "42",
+        "email": "alice@example.org",
+        "email_verified": true,
+    });
+
+    for (claim, value) in [
+        ("iss", json!("http://other")),
+        ("aud", json!("other")),
+        ("exp", json!(Utc::now().timestamp() - 1)),
+        ("roles", json!("team")),
+        ("nonce", json!("nonce of another login")),
+        ("email_verified", json!(false)),
+        ("email_verified", json!("true")),
+    ] {
+        let mut claims = valid.clone();
+        claims[claim] = value;
+        *idp.claims.lock().unwrap() = claims;
+
+        let flow = authorize(&idp, &client).await;
+        assert!(
+            client.finish(&flow, "code").await.is_err(),
+            "Token with invalid '{claim}' was accepted"
+        );
+    }
+
+    *idp.claims.lock().unwrap() = valid;
+    let flow = authorize(&idp, &client).await;
+    assert!(client.finish(&flow, "code").await.is_ok());
+}
+
+#[tokio::test]
+async fn oidc_login_is_linked_to_user_with_same_email() {
+    let (mut generator, registry) = create_registry().await;
+    let ldap_user = generator.generate_user().await;
+    let idp = start_idp().await;
+    let client = OidcClient::new(settings(&idp), registry);
+    *idp.claims.lock().unwrap() = json!({
+        "iss": idp.issuer,
+        "aud": "tachikoma",
+        "exp": Utc::now().timestamp() + 60,
+        "sub": "42",
+        "email": ldap_user.email.to_uppercase(),
+        "email_verified": true,
+        "roles": ["team"],
+    });
+
+    for _ in 0..2 {
+        let flow = authorize(&idp, &client).await;
+        let info = client.finish(&flow, "code").await.unwrap();
+        assert_eq!(info.dn, ldap_user.dn);
+    }
+    let users: i64 = sqlx::query_scalar("SELECT count(*) FROM users")
+        .fetch_one(&generator.pool)
+        .await
+        .unwrap();
+    assert_eq!(users, 1);
+
+    // claims are kept for the linked user
+    let saved = client.get_user_info(&ldap_user.dn).await.unwrap().unwrap();
+    assert_eq!(saved.groups, vec!["team"]);
+}
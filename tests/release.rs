@@ -0,0 +1,63 @@
+pub mod support;
+
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use tachikoma::logic::{
+    events::{HostsEvent, HostsEvents},
+    message_senders::DisabledMessageSender,
+    notifications::Notifier,
+    release::hosts_release_timer,
+};
+use tokio::time::{Instant, sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::support::registry::create_registry;
+
+#[tokio::test]
+async fn lease_is_released_when_it_expires() {
+    let (mut generator, registry) = create_registry().await;
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+
+    let shutdown = CancellationToken::new();
+    let notifier = Notifier::new(registry.clone(), DisabledMessageSender);
+    let events = HostsEvents::listen(&registry, shutdown.clone())
+        .await
+        .unwrap();
+    let mut received = events.subscribe();
+    let leases = async {
+        // nothing is leased, the timer sleeps until a lease changes
+        sleep(Duration::from_millis(300)).await;
+        let mut tx = registry.begin().await.unwrap();
+        tx.lease_hosts(&user.id, &[host.id], Utc::now() + TimeDelta::seconds(1))
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let started = Instant::now();
+        loop {
+            let mut tx = registry.begin().await.unwrap();
+            let leased = tx.get_leased_hosts(&user.id).await.unwrap();
+            tx.commit().await.unwrap();
+            if leased.is_empty() {
+                break;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(3),
+                "Lease wasn't released on expiry"
+            );
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(900));
+        assert_eq!(
+            received.recv().await.unwrap(),
+            HostsEvent::Released(vec![host.id])
+        );
+        shutdown.cancel();
+    };
+    tokio::join!(
+        hosts_release_timer(registry.clone(), &notifier, shutdown.clone()),
+        leases
+    );
+}
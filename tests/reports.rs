@@ -0,0 +1,81 @@
+pub mod support;
+
+use chrono::{TimeDelta, Utc};
+use tachikoma::db::models::LeaseNote;
+use tachikoma::logic::{hosts::HostsService, reports::ReportsService};
+
+use crate::support::registry::create_registry;
+
+#[tokio::test]
+async fn report_is_built_from_lease_history() {
+    let (mut generator, registry) = create_registry().await;
+    let hosts_service = HostsService::new(registry.clone(), 9999);
+    let group = generator.generate_group().await;
+    let first = generator.generate_host_in_group(&group.id).await;
+    let second = generator.generate_host_in_group(&group.id).await;
+    let idle = generator.generate_host_in_group(&group.id).await;
+    let alice = generator.generate_user().await;
+    let bob = generator.generate_user().await;
+
+    let from = Utc::now() - TimeDelta::days(1);
+    hosts_service
+        .lease(
+            &alice.id,
+            &vec![],
+            &[first.id],
+            Some(TimeDelta::hours(2)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    hosts_service
+        .lease(
+            &bob.id,
+            &vec![],
+            &[second.id],
+            Some(TimeDelta::hours(1)),
+            &LeaseNote::default(),
+        )
+        .await
+        .unwrap();
+    hosts_service.free(&bob.id, &[second.id]).await.unwrap();
+
+    let service = ReportsService::new(registry);
+    let report = service
+        .report(from, Utc::now() + TimeDelta::hours(1))
+        .await
+        .unwrap();
+
+    assert_eq!(report.leases, 2);
+    assert_eq!(report.peak, 2);
+    assert_eq!(report.days.first(), Some(&from.date_naive()));
+    let group_report = report
+        .groups
+        .iter()
+        .find(|g| g.group == group.name)
+        .unwrap();
+    assert_eq!(group_report.hosts, 3);
+    assert_eq!(group_report.peak, 2);
+    assert!(group_report.utilisation > 0.0);
+    // open lease counts till the end of the period, released one only while it was active
+    assert_eq!(report.top_users.len(), 2);
+    assert!(report.top_users[0].leased_time > TimeDelta::minutes(59));
+    assert!(report.top_users[1].leased_time < TimeDelta::minutes(1));
+    assert_eq!(
+        report
+            .idle_hosts
+            .iter()
+            .map(|h| h.hostname.as_str())
+            .collect::<Vec<_>>(),
+        vec![idle.hostname.as_str()]
+    );
+
+    let csv = service
+        .history_csv(from, Utc::now() + TimeDelta::hours(1))
+        .await
+        .unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("hostname,ip_address,group,user"));
+    assert!(lines[1].starts_with(&first.hostname));
+}
@@ -0,0 +1,66 @@
+pub mod support;
+
+use std::collections::HashMap;
+
+use tachikoma::db::sessions::PgSessionStore;
+use tower_sessions::{
+    ExpiredDeletion, SessionStore,
+    cookie::time::{Duration, OffsetDateTime},
+    session::{Id, Record},
+};
+
+use crate::support::registry::create_registry;
+
+fn record(expires_in: Duration) -> Record {
+    Record {
+        id: Id::default(),
+        data: HashMap::from([("user".into(), serde_json::json!(42))]),
+        expiry_date: OffsetDateTime::now_utc() + expires_in,
+    }
+}
+
+#[tokio::test]
+async fn sessions_are_stored_in_database() {
+    let (_, registry) = create_registry().await;
+    let store = PgSessionStore::new(&registry);
+    let mut session = record(Duration::days(1));
+
+    store.create(&mut session).await.unwrap();
+    let loaded = store.load(&session.id).await.unwrap().unwrap();
+    assert_eq!(loaded.data, session.data);
+
+    // sessions survive a new store on the same database, e.g. after restart
+    session
+        .data
+        .insert("theme".into(), serde_json::json!("dark"));
+    store.save(&session).await.unwrap();
+    let loaded = PgSessionStore::new(&registry)
+        .load(&session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(loaded.data.len(), 2);
+
+    store.delete(&session.id).await.unwrap();
+    assert!(store.load(&session.id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn expired_sessions_are_deleted() {
+    let (generator, registry) = create_registry().await;
+    let store = PgSessionStore::new(&registry);
+    let mut expired = record(Duration::seconds(-1));
+    let mut active = record(Duration::days(1));
+    store.create(&mut expired).await.unwrap();
+    store.create(&mut active).await.unwrap();
+
+    assert!(store.load(&expired.id).await.unwrap().is_none());
+    store.delete_expired().await.unwrap();
+
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM sessions")
+        .fetch_one(&generator.pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+    assert!(store.load(&active.id).await.unwrap().is_some());
+}
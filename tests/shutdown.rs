@@ -0,0 +1,41 @@
+pub mod support;
+
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use tachikoma::logic::{
+    message_senders::DisabledMessageSender, notifications::Notifier, release::hosts_release_timer,
+};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use crate::support::registry::create_registry;
+
+#[tokio::test]
+async fn release_timer_finishes_tick_on_shutdown() {
+    let (mut generator, registry) = create_registry().await;
+    let host = generator.generate_host().await;
+    let user = generator.generate_user().await;
+    let mut tx = registry.begin().await.unwrap();
+    tx.lease_hosts(&user.id, &[host.id], Utc::now() - TimeDelta::minutes(1))
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let shutdown = CancellationToken::new();
+    shutdown.cancel();
+    let notifier = Notifier::new(registry.clone(), DisabledMessageSender {});
+    timeout(
+        Duration::from_secs(5),
+        hosts_release_timer(registry.clone(), &notifier, shutdown),
+    )
+    .await
+    .expect("Release timer didn't stop");
+
+    let mut tx = registry.begin().await.unwrap();
+    assert!(tx.get_leased_hosts(&user.id).await.unwrap().is_empty());
+    tx.commit().await.unwrap();
+
+    registry.close().await;
+    assert!(registry.begin().await.is_err());
+}
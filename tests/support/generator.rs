@@ -20,8 +20,16 @@ pub struct MockGroup {
     pub id: GroupId,
     pub name: String,
 }
+#[derive(Default)]
+pub struct MockPolicy {
+    pub max_lease_hours: Option<i32>,
+    pub default_lease_hours: Option<i32>,
+    pub user_limit: Option<i32>,
+}
 pub struct MockUser {
     pub id: UserId,
+    pub email: String,
+    pub dn: String,
     pub tg_handle: String,
 }
 
@@ -37,6 +45,48 @@ impl Generator {
             name,
         }
     }
+    pub async fn generate_subgroup(&mut self, parent_id: &GroupId) -> MockGroup {
+        let name = Uuid::new_v4().to_string();
+        let row = sqlx::query!(
+            "INSERT INTO groups (name, parent_id) VALUES ($1, $2) RETURNING id",
+            name,
+            parent_id.0,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .unwrap();
+        MockGroup {
+            id: row.id.into(),
+            name,
+        }
+    }
+    pub async fn set_group_policy(&mut self, group_id: &GroupId, policy: MockPolicy) {
+        sqlx::query!(
+            r#"
+            INSERT INTO group_lease_policies (group_id, max_lease_hours, default_lease_hours, user_limit)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            group_id.0,
+            policy.max_lease_hours,
+            policy.default_lease_hours,
+            policy.user_limit,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+    }
+    pub async fn set_group_access(&mut self, group_id: &GroupId, ad_groups: &[&str]) {
+        for ad_group in ad_groups {
+            sqlx::query!(
+                "INSERT INTO group_access (group_id, ad_group) VALUES ($1, $2)",
+                group_id.0,
+                ad_group,
+            )
+            .execute(&self.pool)
+            .await
+            .unwrap();
+        }
+    }
     pub async fn generate_host(&mut self) -> MockHost {
         self.generate_host_in_group(&GroupId(0)).await
     }
@@ -69,9 +119,9 @@ impl Generator {
 
         let row = sqlx::query!(
             "INSERT INTO users (email, tg_handle, dn) VALUES ($1, $2, $3) RETURNING id",
-            mail,
-            tg_handle,
-            dn,
+            &mail,
+            &tg_handle,
+            &dn,
         )
         .fetch_one(&self.pool)
         .await
@@ -79,6 +129,8 @@ impl Generator {
 
         MockUser {
             id: row.id.into(),
+            email: mail,
+            dn,
             tg_handle,
         }
     }
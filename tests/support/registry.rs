@@ -1,13 +1,21 @@
-use tachikoma::{db::Registry, logic::hosts::HostsService};
+use tachikoma::{
+    db::Registry,
+    logic::{events::HostsEvents, hosts::HostsService, inventory::InventoryService},
+};
+use tokio_util::sync::CancellationToken;
 
 use super::{configure_db, generator::Generator, setup_settings};
 
 pub async fn create_service() -> (Generator, HostsService) {
     let configuration = setup_settings();
     let pool = configure_db(&configuration.database).await;
+    let registry = Registry::new(&configuration.database).await.unwrap();
+    let events = HostsEvents::listen(&registry, CancellationToken::new())
+        .await
+        .unwrap();
     (
         Generator { pool },
-        HostsService::new(Registry::new(&configuration.database).await.unwrap(), 9999),
+        HostsService::new(registry, 9999).with_events(events),
     )
 }
 
@@ -28,3 +36,14 @@ pub async fn create_registry() -> (Generator, Registry) {
         Registry::new(&configuration.database).await.unwrap(),
     )
 }
+
+pub async fn create_inventory_service() -> (Generator, InventoryService, HostsService) {
+    let configuration = setup_settings();
+    let pool = configure_db(&configuration.database).await;
+    let registry = Registry::new(&configuration.database).await.unwrap();
+    (
+        Generator { pool },
+        InventoryService::new(registry.clone()),
+        HostsService::new(registry, 9999),
+    )
+}
@@ -0,0 +1,72 @@
+pub mod support;
+
+use std::sync::{Arc, Mutex};
+
+use axum::{Router, body::Bytes, extract::State, routing::post};
+use tachikoma::{configuration::TelemetrySettings, telemetry::tracer_provider};
+use tokio::net::TcpListener;
+use tracing::instrument::WithSubscriber;
+use tracing_subscriber::{EnvFilter, prelude::*};
+
+use crate::support::registry::create_service;
+
+/// Collector which keeps bodies of received OTLP requests
+#[derive(Clone, Default)]
+struct MockCollector {
+    requests: Arc<Mutex<Vec<Bytes>>>,
+}
+
+async fn traces(State(collector): State<MockCollector>, body: Bytes) {
+    collector.requests.lock().unwrap().push(body);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hosts_operations_are_exported() {
+    let collector = MockCollector::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = Router::new()
+        .route("/v1/traces", post(traces))
+        .with_state(collector.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let (_, service) = create_service().await;
+    let provider = tracer_provider(&TelemetrySettings {
+        otlp_endpoint: format!("http://{address}/"),
+        service_name: "tachikoma-test".into(),
+        sample_ratio: 1.0,
+        filter: "tachikoma=debug".into(),
+    })
+    .unwrap();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_opentelemetry::layer()
+            .with_tracer(opentelemetry::trace::TracerProvider::tracer(
+                &provider, "test",
+            ))
+            .with_filter(EnvFilter::new("tachikoma=debug")),
+    );
+
+    service
+        .get_available_hosts()
+        .with_subscriber(subscriber)
+        .await
+        .unwrap();
+    // exporter uses blocking client
+    let provider = tokio::task::spawn_blocking(move || {
+        provider.force_flush().unwrap();
+        provider
+    })
+    .await
+    .unwrap();
+
+    let requests = collector.requests.lock().unwrap().clone();
+    assert!(!requests.is_empty());
+    let body = requests.concat();
+    let contains = |needle: &str| body.windows(needle.len()).any(|w| w == needle.as_bytes());
+    assert!(contains("tachikoma-test"));
+    assert!(contains("get_available_hosts"));
+    assert!(contains(env!("CARGO_PKG_VERSION")));
+    tokio::task::spawn_blocking(move || provider.shutdown().unwrap())
+        .await
+        .unwrap();
+}